@@ -2,23 +2,32 @@ use std::fs;
 use std::collections::HashSet;
 
 use std::collections::BTreeMap;
+use std::cmp::Ordering;
 
-use ordered_float::OrderedFloat;
+use rayon::prelude::*;
 
 struct Field {
     data: Vec<u8>,
     width: usize,
-    height: usize
+    height: usize,
+    // In reading order
+    asteroids: Vec<Point>,
+    // An asteroid marked with X
+    station: Option<Point>
 }
 
 impl Field {
     pub fn new(input: &str) -> Field {
-        let rows = input.split("\n").collect::<Vec<&str>>();
+        let rows = input.split("\n").map(|row| row.trim_end_matches('\r')).collect::<Vec<&str>>();
         let width = rows[0].len();
         let height = rows.len();
-        return Field { width, height, data: rows.iter().flat_map(|row| row.chars().map(|p|
-            match p { '#' => 1, '.' => 0, _ => panic!("Unexpected pixel {}", p) }
-        )).collect() };
+        let data: Vec<u8> = rows.iter().flat_map(|row| row.chars().map(|p|
+            match p { '#' | 'X' => 1, '.' => 0, _ => panic!("Unexpected pixel {}", p) }
+        )).collect();
+        let asteroids = (0..data.len()).filter(|&i| data[i] == 1).map(|i| Point(i % width, i / width)).collect();
+        let station = rows.iter().enumerate()
+            .find_map(|(y, row)| row.find('X').map(|x| Point(x, y)));
+        return Field { width, height, data, asteroids, station };
     }
 
     pub fn get_pixel(self:&Field, x: usize, y: usize) -> u8 {
@@ -26,104 +35,134 @@ impl Field {
     }
 }
 
-fn visible_asteroids_from(field: &Field, sx: usize, sy: usize) -> usize {
-    let mut angles = HashSet::new();
-    // Run through the asteroids
-    for ay in 0..field.height {
-        for ax in 0..field.width {
-            if ax == sx && ay == sy {
-                // Can't see asteroid you are on top of
-                continue;
-            }
-            if field.get_pixel(ax, ay) == 0 {
-                // No asteroid here
-                continue;
-            }
-            // Calculate angle for each one
-            let angle = (ay as f64 - sy as f64).atan2(ax as f64 - sx as f64);
-            angles.insert(OrderedFloat(angle));
-        }
+fn gcd(a: isize, b: isize) -> isize {
+    return if b == 0 {a.abs()} else {gcd(b, a % b)};
+}
+
+// The direction from one point to another as a step in lowest terms, so every asteroid on the
+// same line of sight gets exactly the same direction however far away it is.
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+struct Direction {
+    dx: isize,
+    dy: isize
+}
+
+impl Direction {
+    // The direction from (sx, sy) to (ax, ay) and how many steps along it (ax, ay) lies
+    fn between(sx: usize, sy: usize, ax: usize, ay: usize) -> (Direction, usize) {
+        let dx = ax as isize - sx as isize;
+        let dy = ay as isize - sy as isize;
+        let steps = gcd(dx, dy);
+        return (Direction { dx: dx / steps, dy: dy / steps }, steps as usize);
+    }
+
+    // Quarters of the clockwise sweep starting from straight up (remember y points down)
+    fn quadrant(&self) -> u8 {
+        return if self.dx >= 0 && self.dy < 0 {0}
+            else if self.dx > 0 && self.dy >= 0 {1}
+            else if self.dx <= 0 && self.dy > 0 {2}
+            else {3};
     }
-    // Count unique angles
-    return angles.len();
+}
+
+// Clockwise order starting from straight up. Within a quadrant, a comes before b when turning
+// from a to b is clockwise, i.e. their cross product is positive.
+impl Ord for Direction {
+    fn cmp(&self, other: &Direction) -> Ordering {
+        return self.quadrant().cmp(&other.quadrant())
+            .then_with(|| (other.dx * self.dy).cmp(&(self.dx * other.dy)));
+    }
+}
+
+impl PartialOrd for Direction {
+    fn partial_cmp(&self, other: &Direction) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+fn visible_asteroids_from(field: &Field, sx: usize, sy: usize) -> usize {
+    return visible_count(&field.asteroids, Point(sx, sy));
+}
+
+fn visible_count(asteroids: &[Point], station: Point) -> usize {
+    // Count unique directions to everything but the station itself
+    return asteroids.iter()
+        .filter(|&&asteroid| asteroid != station)
+        .map(|asteroid| Direction::between(station.0, station.1, asteroid.0, asteroid.1).0)
+        .collect::<HashSet<Direction>>()
+        .len();
 }
 
 #[derive(PartialEq, Debug, Copy, Clone)]
 struct Point(usize, usize);
 
-fn find_best(field: &Field) -> Point {
-    let mut best = Point(0, 0);
-    let mut best_score = 0;
-    for ay in 0..field.height {
-        for ax in 0..field.width {
-            if field.get_pixel(ax, ay) == 0 {
-                // No asteroid to build station here
-                continue;
-            }
-            let score = visible_asteroids_from(field, ax, ay);
-            if score > best_score {
-                best_score = score;
-                best = Point(ax, ay);
-            }
-        }
-    }
-    return best;
+// The asteroid that can see the most others, and how many it sees. Stations are scored in
+// parallel; ties go to the first in reading order.
+fn best_station(asteroids: &[Point]) -> Option<(Point, usize)> {
+    return asteroids.par_iter()
+        .enumerate()
+        .map(|(i, &station)| (i, station, visible_count(asteroids, station)))
+        .max_by(|(i, _, a), (j, _, b)| a.cmp(b).then(j.cmp(i)))
+        .map(|(_, station, score)| (station, score));
 }
 
-const PI_TIMES_2: f64 = 2.0 * std::f64::consts::PI;
+fn find_best(field: &Field) -> Point {
+    return best_station(&field.asteroids).expect("No asteroids to build a station on").0;
+}
 
-fn map_visible_asteroids_from(field: &Field, sx: usize, sy: usize) -> BTreeMap<OrderedFloat<f64>, BTreeMap<OrderedFloat<f64>, Point>> {
+fn map_visible_asteroids_from(field: &Field, sx: usize, sy: usize) -> BTreeMap<Direction, BTreeMap<usize, Point>> {
     let mut roids = BTreeMap::new();
-    // Run through the asteroids
-    for ay in 0..field.height {
-        for ax in 0..field.width {
-            if ax == sx && ay == sy {
-                // Can't see asteroid you are on top of
-                continue;
-            }
-            if field.get_pixel(ax, ay) == 0 {
-                // No asteroid here
-                continue;
-            }
-            // Calculate angle and distance for each one
-            let y = ay as f64 - sy as f64;
-            let x = ax as f64 - sx as f64;
-            let mut angle = (y.atan2(x)) + std::f64::consts::FRAC_PI_2;
-            if angle < 0.0 {
-                angle += PI_TIMES_2;
-            }
-            let d2 = OrderedFloat(x*x + y*y);
-            let target_map = roids.entry(OrderedFloat(angle)).or_insert_with(|| BTreeMap::new());
-            target_map.insert(d2, Point(ax, ay));
+    for &Point(ax, ay) in &field.asteroids {
+        if ax == sx && ay == sy {
+            // Can't see asteroid you are on top of
+            continue;
         }
+        // Calculate direction and distance for each one
+        let (direction, steps) = Direction::between(sx, sy, ax, ay);
+        let target_map = roids.entry(direction).or_insert_with(|| BTreeMap::new());
+        target_map.insert(steps, Point(ax, ay));
     }
     return roids;
 }
 
 
+// The laser's complete schedule as (rotation, asteroid), starting from rotation 0. The nth
+// asteroid out along each direction goes in rotation n, so sorting by rotation then direction
+// gives the order they are hit in.
+fn vaporization_order(field: &Field, station: Point) -> impl Iterator<Item = (usize, Point)> {
+    let roids = map_visible_asteroids_from(field, station.0, station.1);
+    let mut schedule: Vec<(usize, Direction, Point)> = roids.into_iter()
+        .flat_map(|(direction, target_map)| target_map.into_iter()
+            .enumerate()
+            .map(move |(rotation, (_, point))| (rotation, direction, point)))
+        .collect();
+    schedule.sort_by(|(r1, d1, _), (r2, d2, _)| r1.cmp(r2).then(d1.cmp(d2)));
+    return schedule.into_iter().map(|(rotation, _, point)| (rotation, point));
+}
+
 fn vaporised_at(field: &Field, x:usize, y: usize, when: usize) -> Option<Point> {
-    let mut roids = map_visible_asteroids_from(field, x, y);
-    let mut found = 0;
-    let mut any = true;
-    while any {
-        any = false;
-        for (angle, target_map) in roids.iter_mut() {
-            if target_map.len() == 0 {
-                continue;
-            }
-            let (target_key, target_value) = target_map.iter_mut().next()?;
-            println!("Target angle {} distance {} point {:#?}", angle, target_key.sqrt(), target_value);
-            any = true;
-            found += 1;
-            if found == when {
-                return Some(*target_value);
-            } else {
-                let key_copy = *target_key;
-                target_map.remove(&key_copy);
-            }
-        }
-    }
-    return None;
+    return vaporization_order(field, Point(x, y)).nth(when.checked_sub(1)?).map(|(_, point)| point);
+}
+
+// One frame of the sweep: X is the station, * the asteroid just hit and # those still left.
+fn render(field: &Field, station: Point, destroyed: &HashSet<(usize, usize)>, target: Option<Point>) -> String {
+    return (0..field.height).map(|y| (0..field.width).map(|x|
+        if Point(x, y) == station {'X'}
+        else if Some(Point(x, y)) == target {'*'}
+        else if field.get_pixel(x, y) == 1 && !destroyed.contains(&(x, y)) {'#'}
+        else {'.'}
+    ).collect::<String>()).collect::<Vec<String>>().join("\n");
+}
+
+// Every frame of the sweep, each captioned with the shot it shows.
+fn frames(field: &Field, station: Point) -> Vec<String> {
+    let mut destroyed = HashSet::new();
+    return vaporization_order(field, station).enumerate().map(|(i, (rotation, target))| {
+        let frame = format!("Shot {} (rotation {}) at {},{}\n{}", i + 1, rotation + 1, target.0, target.1,
+            render(field, station, &destroyed, Some(target)));
+        destroyed.insert((target.0, target.1));
+        frame
+    }).collect();
 }
 
 fn main() {
@@ -159,7 +198,7 @@ fn main() {
         ).collect::<Vec<&str>>().join(""));
     }*/
 
-    let best = find_best(&image);
+    let best = image.station.unwrap_or_else(|| find_best(&image));
 
     println!("{:#?}", best);
 
@@ -170,6 +209,16 @@ fn main() {
     let two_hundredth = vaporised_at(&image, best.0, best.1, 200);
 
     println!("{:#?}", two_hundredth);
+
+    // `cargo run -- sweep [delay in ms]` animates the laser in the terminal
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("sweep") {
+        let delay = args.get(2).map_or(50, |delay| delay.parse().expect("Delay should be in milliseconds"));
+        for frame in frames(&image, best) {
+            println!("\x1b[2J\x1b[H{}", frame);
+            std::thread::sleep(std::time::Duration::from_millis(delay));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -216,4 +265,95 @@ mod tests {
 ###");
         assert_eq!(vaporised_at(&field, 1, 1, 8), Some(Point(0, 0)));
     }
+
+    #[test]
+    fn test_direction() {
+        assert_eq!(Direction::between(1, 7, 5, 1), (Direction { dx: 2, dy: -3 }, 2));
+        assert_eq!(Direction::between(3, 3, 3, 0), (Direction { dx: 0, dy: -1 }, 3));
+
+        // Sorted by direction should match sorted by clockwise angle from up
+        let mut directions: Vec<Direction> = (-4..=4isize).flat_map(|dx| (-4..=4isize).map(move |dy| (dx, dy)))
+            .filter(|&(dx, dy)| (dx, dy) != (0, 0) && gcd(dx, dy) == 1)
+            .map(|(dx, dy)| Direction { dx, dy })
+            .collect();
+        directions.sort();
+        let angle = |d: &Direction| {
+            let angle = (d.dx as f64).atan2(-d.dy as f64);
+            if angle < 0.0 {angle + 2.0 * std::f64::consts::PI} else {angle}
+        };
+        assert!(directions.windows(2).all(|w| angle(&w[0]) < angle(&w[1])));
+        assert_eq!(directions[0], Direction { dx: 0, dy: -1 });
+    }
+
+    #[test]
+    fn test_distant_collinear() {
+        // Nearly parallel lines of sight a long way out
+        let mut rows = vec![".".repeat(2001); 3];
+        for &(x, y) in &[(0, 0), (1000, 1), (2000, 2), (1999, 2)] {
+            rows[y].replace_range(x..=x, "#");
+        }
+        let field = Field::new(&rows.join("\n"));
+        assert_eq!(visible_asteroids_from(&field, 0, 0), 2);
+    }
+
+    #[test]
+    fn test_vaporization_order() {
+        let field = Field::new(r".#....#####...#..
+##...##.#####..##
+##...#...#.#####.
+..#.....#...###..
+..#.#.....#....##");
+        let order: Vec<(usize, Point)> = vaporization_order(&field, Point(8, 3)).collect();
+        assert_eq!(order.len(), 36);
+        assert_eq!(order[..3], [(0, Point(8, 1)), (0, Point(9, 0)), (0, Point(9, 1))]);
+        assert_eq!(order[30..], [(1, Point(8, 0)), (1, Point(10, 1)), (1, Point(14, 0)), (1, Point(16, 1)), (1, Point(13, 3)), (2, Point(14, 3))]);
+        assert_eq!(vaporised_at(&field, 8, 3, 0), None);
+        assert_eq!(vaporised_at(&field, 8, 3, 37), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        let field = Field::new(r"###
+###
+###");
+        let frames = frames(&field, Point(1, 1));
+        assert_eq!(frames.len(), 8);
+        assert_eq!(frames[0], "Shot 1 (rotation 1) at 1,0\n#*#\n#X#\n###");
+        assert_eq!(frames[7], "Shot 8 (rotation 1) at 0,0\n*..\n.X.\n...");
+    }
+
+    #[test]
+    fn test_marked_station() {
+        let field = Field::new(r".#....#####...#..
+##...##.#####..##
+##...#...#.#####.
+..#.....X...###..
+..#.#.....#....##");
+        assert_eq!(field.station, Some(Point(8, 3)));
+        assert_eq!(field.asteroids.len(), 37);
+        assert_eq!(vaporised_at(&field, 8, 3, 36), Some(Point(14, 3)));
+        assert_eq!(Field::new("#.\n.#").station, None);
+    }
+
+    #[test]
+    fn test_best_station() {
+        // Compare against scoring every cell of a pseudo-random field
+        let mut seed: u64 = 12345;
+        let rows: Vec<String> = (0..40).map(|_| (0..40).map(|_| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            if (seed >> 33) % 5 < 2 {'#'} else {'.'}
+        }).collect()).collect();
+        let field = Field::new(&rows.join("\n"));
+
+        let mut expected = (Point(0, 0), 0);
+        for y in 0..field.height {
+            for x in 0..field.width {
+                if field.get_pixel(x, y) == 1 && visible_asteroids_from(&field, x, y) > expected.1 {
+                    expected = (Point(x, y), visible_asteroids_from(&field, x, y));
+                }
+            }
+        }
+        assert_eq!(best_station(&field.asteroids), Some(expected));
+        assert_eq!(best_station(&[]), None);
+    }
 }
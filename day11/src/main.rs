@@ -170,21 +170,58 @@ fn count_visited(machine: &mut Machine) -> usize {
     return painted.len();
 }
 
-fn draw_paint(machine: &mut Machine) {
-    let painted = paint(machine, 1);
-    let minX = painted.keys().map(|k| k.0).min().unwrap();
-    let maxX = painted.keys().map(|k| k.0).max().unwrap();
-    let minY = painted.keys().map(|k| k.1).min().unwrap();
-    let maxY = painted.keys().map(|k| k.1).max().unwrap();
-    for y in minY..=maxY {
-        println!("{}", (minX..=maxX).map(|x|
+fn bounds(points: &[Point]) -> (Point, Point) {
+    let min_x = points.iter().map(|k| k.0).min().unwrap_or(0);
+    let max_x = points.iter().map(|k| k.0).max().unwrap_or(-1);
+    let min_y = points.iter().map(|k| k.1).min().unwrap_or(0);
+    let max_y = points.iter().map(|k| k.1).max().unwrap_or(-1);
+    return (Point(min_x, min_y), Point(max_x, max_y));
+}
+
+fn draw_paint(painted: &HashMap<Point, u8>) {
+    let (min, max) = bounds(&painted.keys().cloned().collect::<Vec<Point>>());
+    for y in min.1..=max.1 {
+        println!("{}", (min.0..=max.0).map(|x|
             match painted.get(&Point(x,y)).unwrap_or(&0) {
-                0 => " ", 1 => "@", _ => panic!("WOT") }
-            ).collect::<Vec<&str>>().join(""));
+                0 => ' ', 1 => '@', _ => '?' }
+            ).collect::<String>());
+    }
+}
+
+// Writes the hull as a binary PGM, each panel becoming a scale x scale square.
+fn write_pgm(painted: &HashMap<Point, u8>, scale: usize, out: &mut dyn Write) -> std::io::Result<()> {
+    let (min, max) = bounds(&painted.keys().cloned().collect::<Vec<Point>>());
+    let width = (max.0 - min.0 + 1) as usize * scale;
+    let height = (max.1 - min.1 + 1) as usize * scale;
+    let brightest = *painted.values().max().unwrap_or(&1).max(&1) as usize;
+    write!(out, "P5\n{} {}\n255\n", width, height)?;
+    let mut row = Vec::with_capacity(width);
+    for y in min.1..=max.1 {
+        row.clear();
+        for x in min.0..=max.0 {
+            let color = *painted.get(&Point(x, y)).unwrap_or(&0) as usize;
+            row.resize(row.len() + scale, (color * 255 / brightest) as u8);
+        }
+        for _ in 0..scale {
+            out.write_all(&row)?;
+        }
     }
+    return Ok(());
+}
+
+fn bitmap(painted: &HashMap<Point, u8>) -> Vec<Vec<bool>> {
+    let (min, max) = bounds(&painted.keys().cloned().collect::<Vec<Point>>());
+    return (min.1..=max.1).map(|y|
+        (min.0..=max.0).map(|x| painted.get(&Point(x, y)) == Some(&1)).collect()
+    ).collect();
 }
 
 fn main() {
+    // `cargo run -- [path] [scale]` picks where the registration image goes and how big it is
+    let args: Vec<String> = std::env::args().collect();
+    let path = args.get(1).map_or("registration.pgm", |path| path.as_str());
+    let scale = args.get(2).map_or(10, |scale| scale.parse().expect("Scale should be a number"));
+
     let file = File::open("input").expect("Failed to open input");
     let reader = BufReader::new(file);
     for line in reader.lines() {
@@ -205,7 +242,16 @@ fn main() {
 
         machine = Machine::new(parse(&line));
         
-        draw_paint(&mut machine);
+        let painted = paint(&mut machine, 1);
+        draw_paint(&painted);
+
+        let mut image = File::create(path).expect("Failed to create image");
+        write_pgm(&painted, scale, &mut image).expect("Failed to write image");
+
+        match glyphs::read(&bitmap(&painted)) {
+            Ok(text) => println!("{}", text),
+            Err(unrecognized) => println!("{}", unrecognized)
+        }
     }
 }
 
@@ -277,4 +323,36 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    fn hull(rows: &[&str]) -> HashMap<Point, u8> {
+        let mut painted = HashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                painted.insert(Point(x as i32, y as i32), if c == '#' {1} else {0});
+            }
+        }
+        return painted;
+    }
+
+    #[test]
+    fn test_pgm() {
+        let mut painted = hull(&["#.", ".#"]);
+        painted.insert(Point(0, 1), 2);
+        let mut out = Vec::new();
+        write_pgm(&painted, 2, &mut out).unwrap();
+        assert_eq!(out[..11].to_vec(), b"P5\n4 4\n255\n".to_vec());
+        assert_eq!(out[11..].to_vec(), vec![
+            127, 127, 0, 0,
+            127, 127, 0, 0,
+            255, 255, 127, 127,
+            255, 255, 127, 127
+        ]);
+    }
+
+    #[test]
+    fn test_registration() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let mut machine = Machine::new(parse(&line));
+        assert_eq!(glyphs::read(&bitmap(&paint(&mut machine, 1))), Ok("BFEAGHAF".to_string()));
+    }
 }
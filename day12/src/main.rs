@@ -1,99 +1,212 @@
 use std::fs;
+use std::fmt;
+use std::io::{self, Write};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Mul, Sub};
+use std::str::FromStr;
 
-use std::collections::HashMap;
+// The integer types a system can be simulated in.
+trait Int: Copy + Ord + Default + fmt::Debug + fmt::Display + FromStr
+    + Add<Output = Self> + AddAssign + Sub<Output = Self> + Mul<Output = Self> + Sum {
+    fn from_count(n: usize) -> Self;
+    fn abs(self) -> Self;
+}
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {
+        $(impl Int for $t {
+            fn from_count(n: usize) -> $t {
+                return n as $t;
+            }
 
-#[macro_use] extern crate lazy_static;
-use regex::Regex;
+            fn abs(self) -> $t {
+                return <$t>::abs(self);
+            }
+        })*
+    }
+}
 
-#[derive(PartialEq, Debug, Copy, Clone, Hash, Eq)]
-struct Vector(i32, i32, i32);
+impl_int!(i32, i64, i128);
 
-#[derive(PartialEq, Debug, Copy, Clone, Hash, Eq)]
-struct Orbit {
-    position: Vector,
-    velocity: Vector
+// Bodies in D dimensions, stored an axis at a time: positions[axis][body].
+#[derive(PartialEq, Debug, Clone)]
+struct System<T, const D: usize> {
+    positions: [Vec<T>; D],
+    velocities: [Vec<T>; D]
 }
 
-impl Orbit {
-    pub fn new(input: &str) -> Orbit {
-        lazy_static! {
-            static ref PARSE_COORDINATES: Regex = Regex::new(r"<x=(-?\d*), y=(-?\d*), z=(-?\d*)>").unwrap();
+// The change in velocity gravity gives each body along one axis: +1 for every body further
+// along it, -1 for every body behind. Sorting lets each body count these in one pass.
+fn gravity<T: Int>(positions: &[T]) -> Vec<T> {
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by_key(|&i| positions[i]);
+    let mut pulls = vec![T::default(); positions.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start;
+        while end < order.len() && positions[order[end]] == positions[order[start]] {
+            end += 1;
         }
-
-        for cap in PARSE_COORDINATES.captures_iter(input) {
-            return Orbit { position: Vector(cap[1].parse().unwrap(), cap[2].parse().unwrap(), cap[3].parse().unwrap()), velocity: Vector(0, 0, 0) };
+        let pull = T::from_count(order.len() - end) - T::from_count(start);
+        for &i in &order[start..end] {
+            pulls[i] = pull;
         }
-        panic!("{} is not an orbit", input);
+        start = end;
     }
+    return pulls;
 }
 
-fn offset(a: i32, b: i32) -> i32 {
-    return if a > b {-1} else if a < b {1} else {0};
+fn step_axis<T: Int>(positions: &mut [T], velocities: &mut [T]) {
+    for (velocity, pull) in velocities.iter_mut().zip(gravity(positions)) {
+        *velocity += pull;
+    }
+    for (position, velocity) in positions.iter_mut().zip(velocities.iter()) {
+        *position += *velocity;
+    }
 }
 
-fn apply_step(orbits: &Vec<Orbit>, orbit: &Orbit) -> Orbit {
-    let mut position = orbit.position;
-    let mut velocity = orbit.velocity;
-    for o in orbits.iter() {
-        velocity.0 += offset(orbit.position.0, o.position.0);
-        velocity.1 += offset(orbit.position.1, o.position.1);
-        velocity.2 += offset(orbit.position.2, o.position.2);
-    }
-    position.0 += velocity.0;
-    position.1 += velocity.1;
-    position.2 += velocity.2;
+fn gcd(a: u128, b: u128) -> u128 {
+    return if b == 0 {a} else {gcd(b, a % b)};
+}
 
-    return Orbit { position: position, velocity: velocity };
+fn lcm(a: u128, b: u128) -> u128 {
+    return a / gcd(a, b) * b;
 }
 
-fn step(orbits: Vec<Orbit>) -> Vec<Orbit> {
-    return orbits.iter().map(|o| apply_step(&orbits, o)).collect();
+impl<T: Int, const D: usize> System<T, D> {
+    // Bodies start at the given positions, at rest
+    fn new(bodies: &[[T; D]]) -> System<T, D> {
+        let positions = [(); D].map(|_| vec![]);
+        let mut system = System { positions, velocities: [(); D].map(|_| vec![T::default(); bodies.len()]) };
+        for body in bodies {
+            for axis in 0..D {
+                system.positions[axis].push(body[axis]);
+            }
+        }
+        return system;
+    }
+
+    fn len(&self) -> usize {
+        return self.velocities.first().map_or(0, |v| v.len());
+    }
+
+    fn position(&self, body: usize) -> [T; D] {
+        let mut position = [T::default(); D];
+        for axis in 0..D {
+            position[axis] = self.positions[axis][body];
+        }
+        return position;
+    }
+
+    fn step(&mut self) {
+        for axis in 0..D {
+            step_axis(&mut self.positions[axis], &mut self.velocities[axis]);
+        }
+    }
+
+    // Each body's potential (sum of |position|) and kinetic (sum of |velocity|) energy
+    fn body_energies(&self) -> Vec<(T, T)> {
+        return (0..self.len()).map(|body| (
+            (0..D).map(|axis| self.positions[axis][body].abs()).sum(),
+            (0..D).map(|axis| self.velocities[axis][body].abs()).sum()
+        )).collect();
+    }
+
+    fn energy(&self) -> T {
+        return self.body_energies().into_iter().map(|(potential, kinetic)| potential * kinetic).sum();
+    }
+
+    // The states from this one onwards, a step apart
+    fn states(&self) -> impl Iterator<Item = System<T, D>> {
+        return std::iter::successors(Some(self.clone()), |system| {
+            let mut next = system.clone();
+            next.step();
+            Some(next)
+        });
+    }
+
+    // Steps until one axis first returns to its current state. Each step can be run backwards,
+    // so the first repeated state must be the current one and nothing else needs remembering.
+    fn axis_period(&self, axis: usize) -> u64 {
+        let mut positions = self.positions[axis].clone();
+        let mut velocities = self.velocities[axis].clone();
+        let mut steps = 0;
+        loop {
+            step_axis(&mut positions, &mut velocities);
+            steps += 1;
+            if positions == self.positions[axis] && velocities == self.velocities[axis] {
+                return steps;
+            }
+        }
+    }
+
+    // The axes don't affect each other, so the whole system repeats once every axis does.
+    fn period(&self) -> u128 {
+        return (0..D).map(|axis| self.axis_period(axis) as u128).fold(1, lcm);
+    }
 }
 
-fn sum_abs_l2(v: Vector) -> i32 {
-    return v.0.abs() + v.1.abs() + v.2.abs();
+// A body's starting position, either as in the puzzle (<x=-1, y=0, z=2>) or as CSV (-1,0,2).
+fn parse_body<T: Int, const D: usize>(line: &str) -> Result<[T; D], String> {
+    let line = line.trim();
+    let puzzle = line.starts_with('<') && line.ends_with('>');
+    let fields = if puzzle {&line[1..line.len() - 1]} else {line};
+    let values: Vec<&str> = fields.split(',').map(|field| {
+        let field = field.trim();
+        return if puzzle {field.split_once('=').map_or("", |(_, value)| value)} else {field};
+    }).collect();
+    if values.len() != D {
+        return Err(format!("{} has {} coordinates, expected {}", line, values.len(), D));
+    }
+    let mut body = [T::default(); D];
+    for (axis, value) in values.iter().enumerate() {
+        body[axis] = value.trim().parse().map_err(|_| format!("{} is not a coordinate in {}", value, line))?;
+    }
+    return Ok(body);
 }
 
-fn energy(orbits: &Vec<Orbit>) -> i32 {
-    return orbits.iter().map(|o| sum_abs_l2(o.position) * sum_abs_l2(o.velocity)).sum::<i32>();
+fn parse_system<T: Int, const D: usize>(input: &str) -> Result<System<T, D>, String> {
+    let bodies = input.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(parse_body)
+        .collect::<Result<Vec<[T; D]>, String>>()?;
+    return Ok(System::new(&bodies));
 }
 
-fn cycle_length(orbits: Vec<Orbit>) -> i32 {
-    let mut previous = HashMap::<Vec<Orbit>, i32>::new();
-    previous.insert(orbits.clone(), 0);
-    let mut o = orbits;
-    let mut steps = 0;
-    loop {
-        o = step(o);
-        steps += 1;
-        let prior = previous.get(&o);
-        if let Some(p) = prior {
-            return steps - p;
-        }
-        previous.insert(o.clone(), steps);
+// One CSV row per step: the step, the total potential and kinetic energy, and the system's energy.
+fn write_energy<T: Int, const D: usize>(system: &System<T, D>, steps: usize, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "step,potential,kinetic,energy")?;
+    for (step, state) in system.states().take(steps + 1).enumerate() {
+        let energies = state.body_energies();
+        let potential: T = energies.iter().map(|e| e.0).sum();
+        let kinetic: T = energies.iter().map(|e| e.1).sum();
+        writeln!(out, "{},{},{},{}", step, potential, kinetic, state.energy())?;
     }
+    return Ok(());
 }
 
 fn main() {
-    let orbits: Vec<Orbit> = fs::read_to_string("input").expect("Couldn't read input").trim().split("\n").map(|orbit| Orbit::new(orbit)).collect();
+    let input = fs::read_to_string("input").expect("Couldn't read input");
+    let system: System<i64, 3> = parse_system(&input).expect("Couldn't parse input");
 
-    println!("{:#?}", orbits);
+    println!("{:#?}", system);
 
-    let mut o = orbits.clone();
-    for _ in 0..1000 {
-        o = step(o);
+    let after = system.states().nth(1000).unwrap();
+    for body in 0..after.len() {
+        println!("{:?}", after.position(body));
     }
-    
-    println!("{:#?} Energy: {}", o, energy(&o));
+    println!("Energy: {}", after.energy());
 
-    let x_orbit: Vec<Orbit> = orbits.iter().map(|o| Orbit {position: Vector(o.position.0, 0, 0), velocity: Vector(0, 0, 0) }).collect();
-    
-    let y_orbit: Vec<Orbit> = orbits.iter().map(|o| Orbit {position: Vector(0, o.position.1, 0), velocity: Vector(0, 0, 0) }).collect();
-    
-    let z_orbit: Vec<Orbit> = orbits.iter().map(|o| Orbit {position: Vector(0, 0, o.position.2), velocity: Vector(0, 0, 0) }).collect();
+    println!("{} {} {}", system.axis_period(0), system.axis_period(1), system.axis_period(2));
 
-    println!("{} {} {}", cycle_length(x_orbit), cycle_length(y_orbit), cycle_length(z_orbit));
+    println!("Period: {}", system.period());
 
+    // `cargo run -- energy <steps>` writes the energy over time as CSV
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("energy") {
+        let steps = args.get(2).map_or(1000, |steps| steps.parse().expect("Steps should be a number"));
+        write_energy(&system, steps, &mut io::stdout()).expect("Failed to write energy");
+    }
 }
 
 #[cfg(test)]
@@ -103,11 +216,63 @@ mod tests {
 
     #[test]
     fn test_cycle_length() {
-        let orbits: Vec<Orbit> = r"<x=-1, y=0, z=2>
+        let system: System<i32, 3> = parse_system(r"<x=-1, y=0, z=2>
 <x=2, y=-10, z=-7>
 <x=4, y=-8, z=8>
-<x=3, y=5, z=-1>".trim().split("\n").map(|orbit| Orbit::new(orbit)).collect();
-        assert_eq!(cycle_length(orbits), 2772);
-    }   
-}
+<x=3, y=5, z=-1>").unwrap();
+        assert_eq!(system.period(), 2772);
+        assert_eq!(system.states().nth(10).unwrap().energy(), 179);
+    }
+
+    #[test]
+    fn test_period() {
+        let system: System<i64, 3> = parse_system(r"<x=-8, y=-10, z=0>
+<x=5, y=5, z=10>
+<x=2, y=-7, z=3>
+<x=9, y=-8, z=-3>").unwrap();
+        assert_eq!(system.period(), 4686774924);
+        assert_eq!(system.states().nth(100).unwrap().energy(), 1940);
+        assert_eq!(lcm(lcm(2028, 5898), 4702), 4686774924);
+    }
 
+    #[test]
+    fn test_gravity() {
+        assert_eq!(gravity(&[3, 5, 3, -1]), vec![0, -3, 0, 3]);
+        assert_eq!(gravity::<i64>(&[]), vec![]);
+    }
+
+    #[test]
+    fn test_parse() {
+        let puzzle: System<i32, 3> = parse_system("<x=-1, y=0, z=2>\n<x=2, y=-10, z=-7>\n").unwrap();
+        let csv: System<i32, 3> = parse_system("-1,0,2\n2, -10, -7").unwrap();
+        assert_eq!(puzzle, csv);
+        assert_eq!(csv.position(1), [2, -10, -7]);
+
+        let flat: System<i128, 2> = parse_system("<x=1, y=2>\n3,4").unwrap();
+        assert_eq!(flat.len(), 2);
+        assert_eq!(parse_system::<i32, 3>("1,2").unwrap_err(), "1,2 has 2 coordinates, expected 3");
+        assert_eq!(parse_system::<i32, 2>("<x=a, y=2>").unwrap_err(), "a is not a coordinate in <x=a, y=2>");
+    }
+
+    #[test]
+    fn test_energy_export() {
+        let system: System<i32, 3> = parse_system("<x=-1, y=0, z=2>\n<x=2, y=-10, z=-7>\n<x=4, y=-8, z=8>\n<x=3, y=5, z=-1>").unwrap();
+        let mut out = vec![];
+        write_energy(&system, 10, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[0], "step,potential,kinetic,energy");
+        assert_eq!(lines[1], "0,51,0,0");
+        assert!(lines[11].starts_with("10,") && lines[11].ends_with(",179"));
+    }
+
+    #[test]
+    fn test_one_dimension() {
+        // Two bodies in a line fall through each other and back again
+        let system: System<i64, 1> = parse_system("0\n3").unwrap();
+        let positions: Vec<[i64; 1]> = system.states().take(5).map(|s| s.position(0)).collect();
+        assert_eq!(positions, vec![[0], [1], [3], [4], [4]]);
+        assert_eq!(system.period(), 8);
+    }
+}
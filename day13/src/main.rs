@@ -2,7 +2,6 @@ use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
 use std::collections::HashMap;
-use std::convert::TryInto;
 
 type Word = i64;
 
@@ -134,95 +133,144 @@ fn parse(line: &str) -> Vec<Word> {
         .expect("Please type a number!")).collect::<Vec<Word>>();
 }
 
-struct Screen {
-    display: Vec<Vec<u8>>,
-    score: Word
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Tile {
+    Empty,
+    Wall,
+    Block,
+    Paddle,
+    Ball,
+    Unknown(Word)
 }
 
-fn get_screen(machine: &mut Machine, mut input: &mut Vec<Word>, screen: Option<Screen>) -> Screen {
-    let output = run(machine, &mut input);
+impl Tile {
+    fn new(id: Word) -> Tile {
+        return match id {
+            0 => Tile::Empty,
+            1 => Tile::Wall,
+            2 => Tile::Block,
+            3 => Tile::Paddle,
+            4 => Tile::Ball,
+            _ => Tile::Unknown(id)
+        };
+    }
 
-    let mut score = 0;
-    let mut display;
+    fn to_char(self: &Tile) -> char {
+        return match self {
+            Tile::Empty => ' ',
+            Tile::Wall => '+',
+            Tile::Block => '#',
+            Tile::Paddle => '=',
+            Tile::Ball => '@',
+            Tile::Unknown(_) => '?'
+        };
+    }
+}
 
-    if let Some(screen) = screen {
-        display = screen.display;
-        score = screen.score;
-    } else {
-        let mut xs = vec![];
-        let mut ys = vec![];
-        for i in (0..output.len()).step_by(3) {
-            if output[i] == -1 && output[i + 1] == 0 {
-                continue;
-            } else {
-                let x = output[i] as usize;
-                let y = output[i + 1] as usize;
-                xs.push(x);
-                ys.push(y);
-            }
-        }
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+struct Point {
+    x: Word,
+    y: Word
+}
 
-        display = Vec::new();
-        let y_max = *(ys.iter().max().unwrap()) + 1;
-        let x_max = *(xs.iter().max().unwrap()) + 1;
-        display.resize_with(y_max, || {let mut v = Vec::new(); v.resize(x_max, 0); return v});
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Change {
+    BlockDestroyed(Point),
+    Score(Word)
+}
+
+struct Screen {
+    tiles: HashMap<Point, Tile>,
+    min: Point,
+    max: Point,
+    score: Word,
+    // Words of a triple the machine stopped partway through
+    partial: Vec<Word>
+}
+
+impl Screen {
+    fn new() -> Screen {
+        return Screen { tiles: HashMap::new(), min: Point { x: 0, y: 0 }, max: Point { x: 0, y: 0 }, score: 0, partial: vec![] };
+    }
+
+    fn get(self: &Screen, at: Point) -> Tile {
+        return *self.tiles.get(&at).unwrap_or(&Tile::Empty);
     }
 
-    for i in (0..output.len()).step_by(3) {
-        if output[i] == -1 && output[i + 1] == 0 {
-            score = output[i + 2];
+    fn set(self: &mut Screen, at: Point, tile: Tile) -> Option<Change> {
+        if self.tiles.is_empty() {
+            self.min = at;
+            self.max = at;
         } else {
-            let x = output[i] as usize;
-            let y = output[i + 1] as usize;
-            let tile = output[i + 2] as u8;
-            display[y][x] = tile;
+            self.min = Point { x: self.min.x.min(at.x), y: self.min.y.min(at.y) };
+            self.max = Point { x: self.max.x.max(at.x), y: self.max.y.max(at.y) };
         }
+        let previous = self.tiles.insert(at, tile);
+        return if previous == Some(Tile::Block) && tile != Tile::Block {
+            Some(Change::BlockDestroyed(at))
+        } else {
+            None
+        };
     }
 
-    return Screen { display, score };
+    // Apply a frame of (x, y, tile) triples, reporting what changed. The machine can stop
+    // partway through a triple, so any words left over wait for the next frame.
+    fn update(self: &mut Screen, output: &[Word]) -> Vec<Change> {
+        let mut words = std::mem::take(&mut self.partial);
+        words.extend_from_slice(output);
+        let mut changes = vec![];
+        let triples = words.chunks_exact(3);
+        self.partial = triples.remainder().to_vec();
+        for triple in triples {
+            if triple[0] == -1 && triple[1] == 0 {
+                let delta = triple[2] - self.score;
+                self.score = triple[2];
+                if delta != 0 {
+                    changes.push(Change::Score(delta));
+                }
+            } else {
+                let at = Point { x: triple[0], y: triple[1] };
+                if let Some(change) = self.set(at, Tile::new(triple[2])) {
+                    changes.push(change);
+                }
+            }
+        }
+        return changes;
+    }
 }
 
-fn tile_to_char(tile: &u8) -> char {
-    return match tile {
-        0 => ' ',
-        1 => '+',
-        2 => '#',
-        3 => '=',
-        4 => '@',
-        _ => panic!("Unknown tile {}", tile)
-    }
+fn get_screen(machine: &mut Machine, input: &mut Vec<Word>, screen: &mut Screen) -> Vec<Change> {
+    let output = run(machine, input);
+    return screen.update(&output);
 }
 
 fn paint_screen(screen: &Screen) {
     print!("{}[2J", 27 as char);
-    println!("{:>1$}", screen.score, screen.display[0].len());   
-    for row in screen.display.iter() {
-        println!("{}", row.iter().map(tile_to_char).collect::<String>());
+    let width = (screen.max.x - screen.min.x + 1) as usize;
+    println!("{:>1$}", screen.score, width);
+    for y in screen.min.y..=screen.max.y {
+        println!("{}", (screen.min.x..=screen.max.x).map(|x| screen.get(Point { x, y }).to_char()).collect::<String>());
     }
     //std::thread::sleep(std::time::Duration::from_millis(33));
 }
 
-struct Point {
-    x: usize,
-    y: usize
-}
-
-fn find(screen: &Screen, tile: u8) -> Point {
-    for y in 0..screen.display.len() {
-        for x in 0..screen.display[y].len() {
-            if screen.display[y][x] == tile {
-                return Point {x, y};
+fn find(screen: &Screen, tile: Tile) -> Option<Point> {
+    for y in screen.min.y..=screen.max.y {
+        for x in screen.min.x..=screen.max.x {
+            if screen.get(Point { x, y }) == tile {
+                return Some(Point { x, y });
             }
         }
     }
-    paint_screen(screen);
-    panic!("Couldn't find tile {}", tile);
+    return None;
 }
 
 fn ai(screen: &Screen) -> Word {
     // Find ball and paddle
-    let ball = find(screen, 4);
-    let paddle = find(screen, 3);
+    let (ball, paddle) = match (find(screen, Tile::Ball), find(screen, Tile::Paddle)) {
+        (Some(ball), Some(paddle)) => (ball, paddle),
+        _ => return 0
+    };
 
     // Move paddle towards ball
     return if ball.x < paddle.x {
@@ -234,8 +282,8 @@ fn ai(screen: &Screen) -> Word {
     }
 }
 
-fn count_blocks(screen: &Screen) -> i32 {
-    return screen.display.iter().map(|r| r.iter().map(|t| if *t == 2 {1} else {0}).sum::<i32>()).sum::<i32>();
+fn count_blocks(screen: &Screen) -> usize {
+    return screen.tiles.values().filter(|t| **t == Tile::Block).count();
 }
 
 fn main() {
@@ -248,14 +296,15 @@ fn main() {
 
         let mut input = vec![];
 
-        let mut screen = get_screen(&mut machine, &mut input, None);
+        let mut screen = Screen::new();
+        get_screen(&mut machine, &mut input, &mut screen);
 
         paint_screen(&screen);
 
         while count_blocks(&screen) > 0 {
             input = vec![ai(&screen)];
 
-            screen = get_screen(&mut machine, &mut input, Some(screen));
+            get_screen(&mut machine, &mut input, &mut screen);
 
             paint_screen(&screen);
         }
@@ -330,4 +379,52 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    #[test]
+    fn test_screen_grows() {
+        let mut screen = Screen::new();
+        screen.update(&[1, 1, 1, 2, 1, 2]);
+        screen.update(&[40, 30, 3, -5, 2, 4]);
+        assert_eq!(screen.min, Point { x: -5, y: 1 });
+        assert_eq!(screen.max, Point { x: 40, y: 30 });
+        assert_eq!(find(&screen, Tile::Paddle), Some(Point { x: 40, y: 30 }));
+        assert_eq!(find(&screen, Tile::Ball), Some(Point { x: -5, y: 2 }));
+        assert_eq!(screen.get(Point { x: 7, y: 7 }), Tile::Empty);
+    }
+
+    #[test]
+    fn test_screen_partial_frame() {
+        let mut screen = Screen::new();
+        screen.update(&[1, 1, 1, 2, 1]);
+        assert_eq!(screen.get(Point { x: 2, y: 1 }), Tile::Empty);
+        screen.update(&[4, -1]);
+        assert_eq!(find(&screen, Tile::Ball), Some(Point { x: 2, y: 1 }));
+        assert_eq!(screen.update(&[0, 12]), vec![Change::Score(12)]);
+        assert_eq!(screen.partial, vec![]);
+    }
+
+    #[test]
+    fn test_screen_missing_tiles() {
+        let mut screen = Screen::new();
+        screen.update(&[0, 0, 1, 1, 0, 9]);
+        assert_eq!(screen.get(Point { x: 1, y: 0 }), Tile::Unknown(9));
+        assert_eq!(find(&screen, Tile::Ball), None);
+        assert_eq!(ai(&screen), 0);
+    }
+
+    #[test]
+    fn test_screen_changes() {
+        let mut screen = Screen::new();
+        assert_eq!(screen.update(&[1, 0, 2, 2, 0, 2, -1, 0, 0]), vec![]);
+        assert_eq!(count_blocks(&screen), 2);
+        let changes = screen.update(&[1, 0, 0, -1, 0, 12, 2, 0, 4, -1, 0, 20]);
+        assert_eq!(changes, vec![
+            Change::BlockDestroyed(Point { x: 1, y: 0 }),
+            Change::Score(12),
+            Change::BlockDestroyed(Point { x: 2, y: 0 }),
+            Change::Score(8)
+        ]);
+        assert_eq!(screen.score, 20);
+        assert_eq!(count_blocks(&screen), 0);
+    }
 }
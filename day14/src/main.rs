@@ -1,60 +1,91 @@
 use std::fs;
+use std::fmt;
+use std::io::{self, Write};
 
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-#[macro_use] extern crate lazy_static;
-use regex::Regex;
-
 #[derive(PartialEq, Debug, Clone, Hash, Eq)]
-struct Product(String, i32);
+struct Product(String, u64);
 
 type ProductSet = Vec<Product>;
 
 #[derive(PartialEq, Debug, Clone, Hash, Eq)]
-struct ReactionData(i32, ProductSet);
+struct ReactionData(u64, ProductSet);
 
 type Reactions = HashMap<String, ReactionData>;
 
-fn parse_reactions(input: &str) -> Reactions {
-    lazy_static! {
-        static ref PARSE_PRODUCT: Regex = Regex::new(r"(\d+) ([A-Z]+)").unwrap();
+// "7 A" as a product
+fn parse_product(text: &str) -> Result<Product, String> {
+    let mut parts = text.trim().split(' ');
+    let amount = parts.next().unwrap_or("");
+    let amount: u64 = amount.parse().map_err(|_| format!("{} is not an amount", amount))?;
+    let name = match (parts.next(), parts.next()) {
+        (Some(name), None) if !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase()) => name,
+        _ => return Err(format!("{} is not an amount and a chemical", text.trim()))
+    };
+    if amount == 0 {
+        return Err(format!("{} has an amount of zero", text.trim()));
     }
+    return Ok(Product(name.to_string(), amount));
+}
 
+fn parse_reactions(input: &str) -> Result<Reactions, String> {
     let mut reactions = Reactions::new();
 
-    for reaction in input.split("\n") {
+    for (number, reaction) in input.lines().enumerate() {
+        let describe = |error: String| format!("Line {}: {}", number + 1, error);
 
-        let mut products = ProductSet::new();
+        let mut sides = reaction.split("=>");
+        let (inputs, output) = match (sides.next(), sides.next(), sides.next()) {
+            (Some(inputs), Some(output), None) => (inputs, output),
+            _ => return Err(describe(format!("{} is not a reaction", reaction)))
+        };
+        let products = inputs.split(',').map(parse_product).collect::<Result<ProductSet, String>>().map_err(describe)?;
+        let finalProduct = parse_product(output).map_err(describe)?;
 
-        for cap in PARSE_PRODUCT.captures_iter(reaction) {
-            products.push(Product(cap[2].to_string(), cap[1].parse().unwrap())); 
+        if reactions.contains_key(&finalProduct.0) {
+            return Err(describe(format!("{} is already made by another reaction", finalProduct.0)));
         }
-
-        let finalProduct = products.pop().unwrap();
-
         reactions.insert(finalProduct.0, ReactionData(finalProduct.1, products));
     }
 
-    return reactions;
+    return Ok(reactions);
+}
+
+// What running the reactions for some FUEL takes and leaves over
+#[derive(PartialEq, Debug)]
+struct Production {
+    ore: u64,
+    // How much of each chemical is used, including the FUEL itself
+    needed: BTreeMap<String, u64>,
+    // Chemicals made but not used, because reactions only run in whole multiples
+    leftovers: BTreeMap<String, u64>
 }
 
-fn reverse_reactions(reactions: &Reactions, order: &Vec<String>) -> i32 {
-    // Get to ore
-    let mut reactants = HashMap::<String, i32>::new();
+fn produce(reactions: &Reactions, order: &[String], fuel: u64) -> Production {
+    // Get to ore, finishing each chemical's total need before making it as the order puts
+    // everything that uses it first
+    let mut reactants = HashMap::<String, u64>::new();
+    let mut leftovers = BTreeMap::new();
+    let mut needed = BTreeMap::new();
 
-    reactants.insert("FUEL".to_string(), 1);
+    reactants.insert("FUEL".to_string(), fuel);
 
     for name in order.iter().rev() {
-        let amount = reactants.get(name).unwrap();
-        println!("Need {} of {}", amount, name);
+        let amount = reactants.get(name).cloned().unwrap_or(0);
+        needed.insert(name.to_string(), amount);
 
         if name == "ORE" {
-            return *amount;
+            return Production { ore: amount, needed, leftovers };
         }
 
         let reaction = reactions.get(name).unwrap();
         let multiple = (amount + reaction.0 - 1) / reaction.0; // Round up
+        if multiple * reaction.0 > amount {
+            leftovers.insert(name.to_string(), multiple * reaction.0 - amount);
+        }
         for input in reaction.1.iter() {
             *reactants.entry(input.0.to_string()).or_insert(0) += input.1 * multiple;
         }
@@ -63,83 +94,190 @@ fn reverse_reactions(reactions: &Reactions, order: &Vec<String>) -> i32 {
     panic!("Never got to ORE");
 }
 
-fn topo_sort(reactions: &Reactions) -> Vec<String> {
+fn ore_for(reactions: &Reactions, order: &[String], fuel: u64) -> u64 {
+    return produce(reactions, order, fuel).ore;
+}
+
+#[derive(PartialEq, Debug)]
+enum GraphError {
+    // Chemicals that each need the next, the last needing the first
+    Cycle(Vec<String>),
+    // A chemical some reaction needs that no reaction makes
+    Missing(String),
+    // Chemicals made by reactions that FUEL never needs
+    Unreachable(Vec<String>)
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            GraphError::Cycle(chemicals) => write!(f, "Reactions form a cycle: {} -> {}", chemicals.join(" -> "), chemicals[0]),
+            GraphError::Missing(chemical) => write!(f, "No reaction makes {}", chemical),
+            GraphError::Unreachable(chemicals) => write!(f, "FUEL never needs {}", chemicals.join(", "))
+        };
+    }
+}
+
+// Every chemical FUEL needs, each after all of its inputs.
+fn topo_sort(reactions: &Reactions) -> Result<Vec<String>, GraphError> {
     struct TopoEnv<'a> {
         reactions: &'a Reactions,
         order: Vec<String>,
-        visited: HashSet<String>
+        visited: HashSet<String>,
+        // The chemicals being visited, to spot a cycle back to one of them
+        path: Vec<String>
     }
 
-    fn depth_first(env: &mut TopoEnv, key: &str) {
+    fn depth_first(env: &mut TopoEnv, key: &str) -> Result<(), GraphError> {
         if env.visited.contains(key) {
-            return;
+            return Ok(());
+        }
+        if let Some(start) = env.path.iter().position(|name| name == key) {
+            return Err(GraphError::Cycle(env.path[start..].to_vec()));
         }
         let reaction = env.reactions.get(key);
         if let Some(reaction) = reaction {
+            env.path.push(key.to_string());
             for input in reaction.1.iter() {
-                depth_first(env, &input.0);
+                depth_first(env, &input.0)?;
             }
+            env.path.pop();
+        } else if key != "ORE" {
+            return Err(GraphError::Missing(key.to_string()));
         }
         env.order.push(key.to_string());
         env.visited.insert(key.to_string());
-    };
+        return Ok(());
+    }
 
-    // Depth-first search from ORE
+    // Depth-first search from FUEL back through the inputs, down to ORE
     let mut env = TopoEnv {
         reactions,
         order: vec![],
-        visited: HashSet::new()
+        visited: HashSet::new(),
+        path: vec![]
     };
-    depth_first(&mut env, "FUEL");
+    depth_first(&mut env, "FUEL")?;
 
-    return env.order;
+    return Ok(env.order);
 }
 
-fn create_maximum_from(ore: i64, reactions: &Reactions, order: &Vec<String>) -> i64 {
-    // Get to ore
-    let mut reactants_needed = HashMap::<String, i64>::new();
-    
-    let mut ore = ore;
-    let mut fuel = 0;
-
-    while ore > 0 {
-        reactants_needed.insert("FUEL".to_string(), 1);
-
-        for name in order.iter().rev() {
-            let amount: &mut i64 = reactants_needed.entry(name.to_string()).or_insert(0);
-            
-            if name == "ORE" {
-                ore -= *amount;
-                *amount = 0;
-                if ore >= 0 {
-                    fuel += 1;
-                }
-                continue;
-            }
+fn unreachable(reactions: &Reactions, order: &[String]) -> Vec<String> {
+    let mut unused: Vec<String> = reactions.keys().filter(|name| !order.contains(name)).cloned().collect();
+    unused.sort();
+    return unused;
+}
 
-            if *amount > 0 {
-                let reaction = reactions.get(name).unwrap();
-                let multiple = (*amount + reaction.0 as i64 - 1) / reaction.0 as i64; // Round up
-                *amount -= reaction.0 as i64 * multiple;
-                for input in reaction.1.iter() {
-                    *reactants_needed.entry(input.0.to_string()).or_insert(0) += input.1 as i64 * multiple;
-                }
-            }
+// The reactions that consume ORE directly and how much of it each uses, biggest first.
+fn bottlenecks(reactions: &Reactions, production: &Production) -> Vec<(String, u64)> {
+    let mut users: Vec<(String, u64)> = reactions.iter().filter_map(|(name, reaction)| {
+        let ore = reaction.1.iter().find(|input| input.0 == "ORE")?;
+        let needed = production.needed.get(name).cloned().unwrap_or(0);
+        let runs = (needed + reaction.0 - 1) / reaction.0;
+        return Some((name.to_string(), runs * ore.1));
+    }).collect();
+    users.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    return users;
+}
+
+// Graphviz with an edge from each input to what it makes, labelled with the amount per reaction.
+// Each chemical is labelled with how much the production used; the biggest ORE user is in red.
+fn write_dot(reactions: &Reactions, production: &Production, out: &mut dyn Write) -> io::Result<()> {
+    let biggest = bottlenecks(reactions, production).first().map(|(name, _)| name.to_string());
+    writeln!(out, "digraph reactions {{")?;
+    for (name, needed) in &production.needed {
+        let label = match reactions.get(name) {
+            Some(reaction) => format!("{}\\n{} needed, {} runs", name, needed, (needed + reaction.0 - 1) / reaction.0),
+            None => format!("{}\\n{} needed", name, needed)
+        };
+        let colour = if Some(name) == biggest.as_ref() {", color=red"} else {""};
+        writeln!(out, "    \"{}\" [label=\"{}\"{}];", name, label, colour)?;
+    }
+    let mut names: Vec<&String> = reactions.keys().collect();
+    names.sort();
+    for name in names {
+        let reaction = &reactions[name];
+        for input in &reaction.1 {
+            writeln!(out, "    \"{}\" -> \"{}\" [label=\"{}\"];", input.0, name, input.1)?;
         }
     }
+    return writeln!(out, "}}");
+}
 
-    return fuel;
+fn graph(reactions: &Reactions) -> Result<(), GraphError> {
+    let order = topo_sort(reactions)?;
+    let unused = unreachable(reactions, &order);
+    if !unused.is_empty() {
+        return Err(GraphError::Unreachable(unused));
+    }
+    let production = produce(reactions, &order, 1);
+    write_dot(reactions, &production, &mut io::stdout()).expect("Failed to write graph");
+
+    eprintln!("ORE bottlenecks for 1 FUEL ({} ORE):", production.ore);
+    for (name, ore) in bottlenecks(reactions, &production) {
+        eprintln!("  {} uses {} ORE ({:.1}%)", name, ore, ore as f64 * 100.0 / production.ore as f64);
+    }
+    return Ok(());
+}
+
+// The most FUEL the ore can make. Doubles until the ore runs out, then binary searches
+// between the last amount that fitted and the first that didn't.
+fn create_maximum_from(ore: u64, reactions: &Reactions, order: &[String]) -> u64 {
+    if ore_for(reactions, order, 1) > ore {
+        return 0;
+    }
+    let mut fits = 1;
+    let mut too_much = 2;
+    while ore_for(reactions, order, too_much) <= ore {
+        fits = too_much;
+        too_much *= 2;
+    }
+    while too_much - fits > 1 {
+        let middle = fits + (too_much - fits) / 2;
+        if ore_for(reactions, order, middle) <= ore {
+            fits = middle;
+        } else {
+            too_much = middle;
+        }
+    }
+    return fits;
 }
 
 fn main() {
-    let reactions: Reactions = parse_reactions(fs::read_to_string("input").expect("Couldn't read input").trim());
+    let reactions: Reactions = match parse_reactions(fs::read_to_string("input").expect("Couldn't read input").trim()) {
+        Ok(reactions) => reactions,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
 
-    println!("{:#?}", reactions);
+    // `cargo run -- graph` writes the reactions as Graphviz and lists the ORE bottlenecks
+    if std::env::args().nth(1).as_deref() == Some("graph") {
+        if let Err(error) = graph(&reactions) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let order = topo_sort(&reactions);
-    println!("{}", reverse_reactions(&reactions, &order));
+    let order = match topo_sort(&reactions) {
+        Ok(order) => order,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", ore_for(&reactions, &order, 1));
 
-    println!("{}", create_maximum_from(1000000000000, &reactions, &order));
+    let fuel = create_maximum_from(1000000000000, &reactions, &order);
+    println!("{}", fuel);
+
+    let production = produce(&reactions, &order, fuel);
+    println!("{} FUEL uses {} ORE, leaving:", fuel, production.ore);
+    for (name, amount) in &production.leftovers {
+        println!("  {} {}", amount, name);
+    }
 }
 
 #[cfg(test)]
@@ -155,9 +293,12 @@ mod tests {
 7 A, 1 C => 1 D
 7 A, 1 D => 1 E
 7 A, 1 E => 1 FUEL";
-        let reactions = parse_reactions(r);
-        let order = topo_sort(&reactions);
-        assert_eq!(reverse_reactions(&reactions, &order), 31);
+        let reactions = parse_reactions(r).unwrap();
+        let order = topo_sort(&reactions).unwrap();
+        assert_eq!(ore_for(&reactions, &order, 1), 31);
+
+        let production = produce(&reactions, &order, 1);
+        assert_eq!(production.leftovers.into_iter().collect::<Vec<_>>(), vec![("A".to_string(), 2)]);
     }
 
     #[test]
@@ -174,9 +315,58 @@ mod tests {
 1 NVRVD => 8 CXFTF
 1 VJHF, 6 MNCFX => 4 RFSQX
 176 ORE => 6 VJHF";
-        let reactions = parse_reactions(r);
-        let order = topo_sort(&reactions);
+        let reactions = parse_reactions(r).unwrap();
+        let order = topo_sort(&reactions).unwrap();
+        assert_eq!(ore_for(&reactions, &order, 1), 180697);
         assert_eq!(create_maximum_from(1000000000000, &reactions, &order), 5586022);
+        assert!(ore_for(&reactions, &order, 5586022) <= 1000000000000);
+        assert!(ore_for(&reactions, &order, 5586023) > 1000000000000);
+        assert_eq!(create_maximum_from(180696, &reactions, &order), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_reactions("10 ORE => 10 A\n7 A, 1 B -> 1 C"), Err("Line 2: 7 A, 1 B -> 1 C is not a reaction".to_string()));
+        assert_eq!(parse_reactions("ten ORE => 10 A"), Err("Line 1: ten is not an amount".to_string()));
+        assert_eq!(parse_reactions("10 ORE => 10 A B"), Err("Line 1: 10 A B is not an amount and a chemical".to_string()));
+        assert_eq!(parse_reactions("10 ORE => 0 A"), Err("Line 1: 0 A has an amount of zero".to_string()));
+        assert_eq!(parse_reactions("10 ORE => 10 A\n1 ORE => 1 A"), Err("Line 2: A is already made by another reaction".to_string()));
+    }
+
+    #[test]
+    fn test_graph_errors() {
+        let reactions = parse_reactions("1 ORE => 1 A\n1 A, 1 C => 1 B\n1 B => 1 C\n1 C => 1 FUEL").unwrap();
+        assert_eq!(topo_sort(&reactions), Err(GraphError::Cycle(vec!["C".to_string(), "B".to_string()])));
+        assert_eq!(topo_sort(&reactions).unwrap_err().to_string(), "Reactions form a cycle: C -> B -> C");
+
+        let reactions = parse_reactions("1 ORE => 1 A\n1 A, 1 D => 1 FUEL").unwrap();
+        assert_eq!(topo_sort(&reactions).unwrap_err().to_string(), "No reaction makes D");
+
+        let reactions = parse_reactions("1 ORE => 1 A\n1 A => 1 FUEL\n1 ORE => 1 Z\n1 Z => 1 Y").unwrap();
+        let order = topo_sort(&reactions).unwrap();
+        assert_eq!(unreachable(&reactions, &order), vec!["Y", "Z"]);
+        assert_eq!(graph(&reactions).unwrap_err().to_string(), "FUEL never needs Y, Z");
     }
-}
 
+    #[test]
+    fn test_dot() {
+        let reactions = parse_reactions(r"10 ORE => 10 A
+1 ORE => 1 B
+7 A, 1 B => 1 C
+7 A, 1 C => 1 D
+7 A, 1 D => 1 E
+7 A, 1 E => 1 FUEL").unwrap();
+        let order = topo_sort(&reactions).unwrap();
+        let production = produce(&reactions, &order, 1);
+        assert_eq!(bottlenecks(&reactions, &production), vec![("A".to_string(), 30), ("B".to_string(), 1)]);
+
+        let mut out = vec![];
+        write_dot(&reactions, &production, &mut out).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot.starts_with("digraph reactions {\n"));
+        assert!(dot.contains("    \"A\" [label=\"A\\n28 needed, 3 runs\", color=red];\n"));
+        assert!(dot.contains("    \"ORE\" [label=\"ORE\\n31 needed\"];\n"));
+        assert!(dot.contains("    \"E\" -> \"FUEL\" [label=\"1\"];\n"));
+        assert_eq!(dot.matches(" -> ").count(), 10);
+    }
+}
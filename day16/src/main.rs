@@ -1,11 +1,7 @@
 use std::fs;
 
-use std::collections::HashMap;
-use std::collections::HashSet;
-
-#[macro_use] extern crate lazy_static;
-use regex::Regex;
-
+// The pattern for one output digit spelled out, to check the prefix sums against
+#[cfg(test)]
 fn phase_array(phase: i32, length: usize) -> Vec<i32> {
     let signals = vec![0, 1, 0, -1];
     let mut output = vec![];
@@ -31,17 +27,34 @@ fn parse_signal(signal: &str) -> Vec<i32> {
     return signal.as_bytes().iter().map(|b| (b - ('0' as u8)) as i32).collect();
 }
 
-fn calculate_phase(signal: &Vec<i32>) -> Vec<i32> {
-    let mut output = vec![];
-    for i in 0..signal.len() {
-        let mut sum: i32 = 0;
-        let coeffs = phase_array(1 + i as i32, signal.len());
-        for j in 0..signal.len() {
-            sum += signal[j] * coeffs[j];
-        }
-        output.push(sum.abs() % 10); // Odd but correct for this challenge
+// One phase applied to the digits of a signal from `offset` on. The pattern for each output digit
+// is zero before that digit, so digits before the offset are never needed. The pattern for the
+// kth digit is made of runs of k, so with prefix sums each run adds in one step and the kth digit
+// takes about n/k steps: O(n log n) for the phase.
+fn calculate_tail_phase(tail: &[i32], offset: usize) -> Vec<i32> {
+    let n = tail.len();
+    let mut prefix = vec![0i64; n + 1];
+    for (i, digit) in tail.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + *digit as i64;
     }
-    return output;
+    // Sum of the digits at signal positions from..to, clipped to the end
+    let sum = |from: usize, to: usize| prefix[(to - offset).min(n)] - prefix[(from - offset).min(n)];
+
+    return (0..n).map(|i| {
+        let k = offset + i + 1;
+        let mut total = 0;
+        // Runs of 1s start at k - 1, -1s 2k later, repeating every 4k
+        let mut start = k - 1;
+        while start < offset + n {
+            total += sum(start, start + k) - sum(start + 2 * k, start + 3 * k);
+            start += 4 * k;
+        }
+        return (total.abs() % 10) as i32; // Odd but correct for this challenge
+    }).collect();
+}
+
+fn calculate_phase(signal: &Vec<i32>) -> Vec<i32> {
+    return calculate_tail_phase(signal, 0);
 }
 
 fn calculate_phases(signal: &Vec<i32>, phase_count: usize) -> Vec<i32> {
@@ -52,6 +65,9 @@ fn calculate_phases(signal: &Vec<i32>, phase_count: usize) -> Vec<i32> {
     return output;
 }
 
+// Suffix sums a phase at a time, which the closed form in calculate_binomial_phases replaces;
+// kept to check it against
+#[cfg(test)]
 fn calculate_fast_phase(mut signal: Vec<i32>) -> Vec<i32> {
     for i in (0..(signal.len() - 1)).rev() {
         signal[i] += signal[i + 1];
@@ -64,6 +80,7 @@ fn calculate_fast_phase(mut signal: Vec<i32>) -> Vec<i32> {
     return signal;
 }
 
+#[cfg(test)]
 fn calculate_fast_phases(signal: &Vec<i32>, phase_count: usize) -> Vec<i32> {
     let mut output: Vec<i32> = signal.iter().cloned().collect();
     for _ in 0..phase_count {
@@ -72,21 +89,93 @@ fn calculate_fast_phases(signal: &Vec<i32>, phase_count: usize) -> Vec<i32> {
     return output;
 }
 
-fn main() {
-    let input: Vec<i32> = parse_signal(fs::read_to_string("input").expect("Couldn't read input").trim());
-    //let output = calculate_phases(&input, 100);
-    //println!("{}", output.iter().take(8).map(|d| d.to_string()).collect::<String>());
+// C(n, k) mod p for a small prime p by Lucas' theorem: the product of the binomials of n and
+// k's base p digits.
+fn binomial_mod_prime(mut n: usize, mut k: usize, p: usize) -> usize {
+    let mut result = 1;
+    while k > 0 {
+        let (ni, ki) = (n % p, k % p);
+        if ki > ni {
+            return 0;
+        }
+        // C(ni, ki) directly, as both are below p
+        let mut c = 1;
+        for i in 0..ki {
+            c = c * (ni - i) / (i + 1);
+        }
+        result = result * c % p;
+        n /= p;
+        k /= p;
+    }
+    return result;
+}
+
+// C(n, k) mod 10, combining mod 2 and mod 5 by the Chinese remainder theorem
+fn binomial_mod10(n: usize, k: usize) -> i32 {
+    return ((5 * binomial_mod_prime(n, k, 2) + 6 * binomial_mod_prime(n, k, 5)) % 10) as i32;
+}
+
+// The first `digits` digits of the tail after `phase_count` rounds of suffix sums. Each round
+// sums everything after a digit, so after p rounds the digit k places along is counted
+// C(p - 1 + k, k) times and the result comes from one pass over the tail.
+fn calculate_binomial_phases(tail: &[i32], phase_count: usize, digits: usize) -> Vec<i32> {
+    let digits = digits.min(tail.len());
+    if phase_count == 0 {
+        return tail[..digits].to_vec();
+    }
+    let mut output = vec![0; digits];
+    for (j, digit) in tail.iter().enumerate() {
+        for (d, out) in output.iter_mut().enumerate().take(j + 1) {
+            *out = (*out + binomial_mod10(phase_count - 1 + j - d, j - d) * digit) % 10;
+        }
+    }
+    return output;
+}
+
+// Where the message starts: the number in the signal's first seven digits
+fn message_offset(signal: &[i32]) -> usize {
+    return signal.iter().take(7).fold(0, |offset, digit| offset * 10 + *digit as usize);
+}
+
+enum Offset {
+    At(usize),
+    FromSignal
+}
 
-    let skip = 5976277;
-    let total = input.len() * 10000;
-    let take = total - skip;
+// The eight digit message after running the phases over the signal repeated `repeat` times.
+// Past halfway through the signal each digit is just the sum of those after it, so the closed
+// form for repeated suffix sums does; otherwise the full pattern is worked out with prefix sums.
+fn fft(signal: &[i32], phases: usize, repeat: usize, offset: Offset) -> Vec<i32> {
+    let offset = match offset {
+        Offset::At(offset) => offset,
+        Offset::FromSignal => message_offset(signal)
+    };
+    let total = signal.len() * repeat;
+    if offset >= total {
+        return vec![];
+    }
+    let mut tail: Vec<i32> = signal.iter().cloned().cycle().skip(offset).take(total - offset).collect();
+    if offset * 2 >= total {
+        return calculate_binomial_phases(&tail, phases, 8);
+    } else {
+        for _ in 0..phases {
+            tail = calculate_tail_phase(&tail, offset);
+        }
+    }
+    tail.truncate(8);
+    return tail;
+}
 
-    println!("{} {} {}", total, skip, take);
+fn to_message(digits: &[i32]) -> String {
+    return digits.iter().map(|d| d.to_string()).collect::<String>();
+}
+
+fn main() {
+    let input: Vec<i32> = parse_signal(fs::read_to_string("input").expect("Couldn't read input").trim());
 
-    let relevant_input: Vec<i32> = input.iter().cloned().cycle().skip(skip).take(take).collect();
+    println!("{}", to_message(&fft(&input, 100, 1, Offset::At(0))));
 
-    let output = calculate_fast_phases(&relevant_input, 100);
-    println!("{}", output.iter().take(8).map(|d| d.to_string()).collect::<String>());
+    println!("{}", to_message(&fft(&input, 100, 10000, Offset::FromSignal)));
 }
 
 #[cfg(test)]
@@ -116,5 +205,69 @@ mod tests {
     fn test_calculate_phases() {
         assert_eq!(calculate_phases(&parse_signal("12345678"), 4), parse_signal("01029498"));
     }
-}
 
+    fn naive_phase(signal: &[i32]) -> Vec<i32> {
+        return (0..signal.len()).map(|i| {
+            let coeffs = phase_array(1 + i as i32, signal.len());
+            signal.iter().zip(coeffs).map(|(s, c)| s * c).sum::<i32>().abs() % 10
+        }).collect();
+    }
+
+    #[test]
+    fn test_prefix_sums_match_pattern() {
+        let signal = parse_signal("80871224585914546619083218645595");
+        let naive = naive_phase(&signal);
+        assert_eq!(calculate_phase(&signal), naive);
+        assert_eq!(calculate_tail_phase(&signal[5..], 5), naive[5..].to_vec());
+    }
+
+    #[test]
+    fn test_fft() {
+        assert_eq!(to_message(&fft(&parse_signal("80871224585914546619083218645595"), 100, 1, Offset::At(0))), "24176176");
+        assert_eq!(to_message(&fft(&parse_signal("03036732577212944063491565474664"), 100, 10000, Offset::FromSignal)), "84462026");
+        assert_eq!(to_message(&fft(&parse_signal("02935109699940807407585447034323"), 100, 10000, Offset::FromSignal)), "78725270");
+        assert_eq!(to_message(&fft(&parse_signal("03081770884921959731165446850517"), 100, 10000, Offset::FromSignal)), "53553731");
+        assert_eq!(fft(&parse_signal("1234"), 1, 2, Offset::At(8)), vec![]);
+    }
+
+    #[test]
+    fn test_fft_first_half() {
+        // An offset before halfway needs the full pattern
+        let signal = parse_signal("19617804207202209144916044189917");
+        let mut expected: Vec<i32> = signal.iter().cloned().cycle().take(signal.len() * 3).collect();
+        for _ in 0..10 {
+            expected = naive_phase(&expected);
+        }
+        assert_eq!(fft(&signal, 10, 3, Offset::At(20)), expected[20..28].to_vec());
+    }
+
+    #[test]
+    fn test_binomial_mod10() {
+        // Against Pascal's triangle
+        let mut row = vec![1];
+        for n in 0..60 {
+            for k in 0..=n {
+                assert_eq!(binomial_mod10(n, k), row[k], "C({}, {})", n, k);
+            }
+            row = (0..=n + 1).map(|k| (if k > 0 {row[k - 1]} else {0} + if k <= n {row[k]} else {0}) % 10).collect();
+        }
+    }
+
+    #[test]
+    fn test_binomial_phases() {
+        let signal = parse_signal("03036732577212944063491565474664");
+        let tail: Vec<i32> = signal.iter().cloned().cycle().take(3000).collect();
+        for &phases in &[0, 1, 2, 7, 100, 125] {
+            assert_eq!(calculate_binomial_phases(&tail, phases, 8), calculate_fast_phases(&tail, phases)[..8].to_vec());
+        }
+        assert_eq!(calculate_binomial_phases(&tail[..3], 4, 8), calculate_fast_phases(&tail[..3].to_vec(), 4));
+    }
+
+    #[test]
+    fn test_many_phases() {
+        // A short tail, as the suffix sums take a pass per phase
+        let signal = parse_signal("02935109699940807407585447034323");
+        let tail: Vec<i32> = signal.iter().cloned().cycle().take(100).collect();
+        assert_eq!(calculate_binomial_phases(&tail, 1000000, 8), calculate_fast_phases(&tail, 1000000)[..8].to_vec());
+    }
+}
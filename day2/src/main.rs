@@ -1,30 +1,276 @@
 use std::fs::File;
-use std::io::{self, prelude::*, BufReader};
+use std::io::{prelude::*, BufReader};
 
-fn run(mem: &mut Vec<usize>) {
-    let mut ip: usize = 0;
+use rayon::prelude::*;
+
+type Word = i64;
+
+struct Machine {
+    mem: Vec<Word>,
+    ip: usize,
+    relative_base: Word
+}
+
+
+impl Machine
+{
+    pub fn new(mem: Vec<Word>) -> Machine {
+        Machine { mem: mem, ip: 0, relative_base: 0 }
+    }
+}
+
+type AddrOrImm = Result<usize, Word>;
+
+fn get_at(machine: &Machine, addr: usize) -> Word {
+    if addr >= machine.mem.len() {
+        return 0;
+    }
+    return machine.mem[addr];
+}
+
+fn to_address(value: Word) -> usize {
+    if value < 0 {
+        panic!("Negative Intcode address {}", value);
+    }
+    return value as usize;
+}
+
+fn addr(machine: &Machine, offset: Word, access: Word) -> AddrOrImm {
+    let mut a = access;
+    for _ in 1..offset {
+        a /= 10;
+    }
+    a = a % 10;
+    let instr_value = get_at(machine, machine.ip + offset as usize);
+    //println!("instr_value: {}, offset: {}, access: {}", instr_value, offset, access);
+    return match a {
+        0 => Ok(to_address(instr_value)),
+        1 => Err(instr_value),
+        2 => Ok(to_address(machine.relative_base.checked_add(instr_value).expect("Intcode overflow in relative address"))),
+        _ => panic!("Unknown access type {} (offset: {} access: {})", a, offset, access)
+    };
+}
+
+fn get(machine: &Machine, offset: Word, access: Word) -> Word {
+    let a = addr(machine, offset, access);
+    return match a {
+        Ok(address) => get_at(machine, address),
+        Err(immediate) => immediate
+    };
+}
+
+fn set(machine: &mut Machine, offset: Word, to: Word) {
+    //println!("Set {} to {}", offset, to);
+    let offset_as_usize = offset as usize;
+    if machine.mem.len() <= offset_as_usize {
+        machine.mem.resize(offset_as_usize + 1, 0);
+    }
+    machine.mem[offset_as_usize] = to;
+}
+
+fn run(machine: &mut Machine, input: &mut Vec<Word>) -> Vec<Word> {
+    let mut output = Vec::new();
     loop {
-        match mem[ip] {
+        let opcode = machine.mem[machine.ip] % 100;
+        let access = machine.mem[machine.ip] / 100;
+        match opcode {
             1 => {
-                let target = mem[ip + 3];
-                mem[target] = mem[mem[ip + 1]] + mem[mem[ip + 2]];
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_add(get(machine, 2, access)).expect("Intcode overflow in add"));
+                machine.ip += 4;
             },
             2 => {
-                let target = mem[ip + 3];
-                mem[target] = mem[mem[ip + 1]] * mem[mem[ip + 2]];
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_mul(get(machine, 2, access)).expect("Intcode overflow in multiply"));
+                machine.ip += 4;
+            },
+            3 => {
+                if input.len() == 0 {
+                    return output;
+                }
+                let target = addr(machine, 1, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, input.remove(0));
+                machine.ip += 2;
+            },
+            4 => {
+                output.push(get(machine, 1, access));
+                machine.ip += 2;
+            },
+            5 => {
+                let val = get(machine, 1, access);
+                if val != 0 {
+                    machine.ip = get(machine, 2, access) as usize;
+                } else {
+                    machine.ip += 3;
+                }
+            },
+            6 => {
+                let val = get(machine, 1, access);
+                if val == 0 {
+                    machine.ip = get(machine, 2, access) as usize;
+                } else {
+                    machine.ip += 3;
+                }
+            },
+            7 => {
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) < get(machine, 2, access) {1} else {0});
+                machine.ip += 4;
+            },
+            8 => {
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) == get(machine, 2, access) {1} else {0});
+                machine.ip += 4;
+            },
+            9 => {
+                machine.relative_base = machine.relative_base.checked_add(get(machine, 1, access)).expect("Intcode overflow in relative base");
+                machine.ip += 2;
             },
             99 => {
-                return;
+                return output;
             },
-            _ => panic!("Incorrect opcode")
+            _ => panic!("Incorrect opcode {}", opcode)
         }
-        ip += 4;
     }
 }
 
-fn parse(line: &str) -> Vec<usize> {
+fn parse(line: &str) -> Vec<Word> {
     return line.split(",").map(|item| item.trim().parse()
-        .expect("Please type a number!")).collect::<Vec<usize>>();
+        .expect("Please type a number!")).collect::<Vec<Word>>();
+}
+
+fn output(program: &[Word], noun: Word, verb: Word) -> Word {
+    let mut machine = Machine::new(program.to_vec());
+    machine.mem[1] = noun;
+    machine.mem[2] = verb;
+    run(&mut machine, &mut vec![]);
+    return machine.mem[0];
+}
+
+// constant + noun * n + verb * v
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Affine {
+    constant: Word,
+    noun: Word,
+    verb: Word
+}
+
+impl Affine {
+    fn constant(constant: Word) -> Affine {
+        return Affine { constant, noun: 0, verb: 0 };
+    }
+
+    fn eval(&self, noun: Word, verb: Word) -> Word {
+        return self.constant + self.noun * noun + self.verb * verb;
+    }
+
+    fn as_constant(&self) -> Option<Word> {
+        return if self.noun == 0 && self.verb == 0 {Some(self.constant)} else {None};
+    }
+
+    fn add(&self, other: &Affine) -> Option<Affine> {
+        return Some(Affine {
+            constant: self.constant.checked_add(other.constant)?,
+            noun: self.noun.checked_add(other.noun)?,
+            verb: self.verb.checked_add(other.verb)?
+        });
+    }
+
+    fn scale(&self, by: Word) -> Option<Affine> {
+        return Some(Affine {
+            constant: self.constant.checked_mul(by)?,
+            noun: self.noun.checked_mul(by)?,
+            verb: self.verb.checked_mul(by)?
+        });
+    }
+
+    // None if both sides depend on the noun or verb, as the product isn't affine
+    fn mul(&self, other: &Affine) -> Option<Affine> {
+        if let Some(by) = self.as_constant() {
+            return other.scale(by);
+        }
+        return other.as_constant().and_then(|by| self.scale(by));
+    }
+}
+
+// Reads a parameter, giving None if its value depends on the noun or verb in a way we can't track.
+fn operand(mem: &[Option<Affine>], ip: usize, offset: usize, access: Word) -> Option<Affine> {
+    let param = mem.get(ip + offset).cloned().flatten()?;
+    if access == 1 {
+        return Some(param);
+    }
+    let address = param.as_constant()?;
+    if address < 0 {
+        return None;
+    }
+    return mem.get(address as usize).cloned().unwrap_or(Some(Affine::constant(0)));
+}
+
+// Runs the program with positions 1 and 2 left as the noun and verb, returning position 0 as an
+// expression in them. None if that isn't possible: the program uses anything but ADD and MUL,
+// writes to an address that depends on the noun or verb, or leaves a non-affine result.
+fn run_symbolic(program: &[Word]) -> Option<Affine> {
+    let mut mem: Vec<Option<Affine>> = program.iter().map(|&word| Some(Affine::constant(word))).collect();
+    mem[1] = Some(Affine { constant: 0, noun: 1, verb: 0 });
+    mem[2] = Some(Affine { constant: 0, noun: 0, verb: 1 });
+    let mut ip = 0;
+    loop {
+        let instruction = mem.get(ip).cloned().flatten()?.as_constant()?;
+        let opcode = instruction % 100;
+        let access = instruction / 100;
+        match opcode {
+            1 | 2 => {
+                if access % 10 > 1 || access / 10 % 10 > 1 || access / 100 != 0 {
+                    return None;
+                }
+                let target = mem.get(ip + 3).cloned().flatten()?.as_constant()?;
+                if target < 0 {
+                    return None;
+                }
+                let a = operand(&mem, ip, 1, access % 10);
+                let b = operand(&mem, ip, 2, access / 10);
+                let value = match (a, b) {
+                    (Some(a), Some(b)) => if opcode == 1 {a.add(&b)} else {a.mul(&b)},
+                    _ => None
+                };
+                if mem.len() <= target as usize {
+                    mem.resize(target as usize + 1, Some(Affine::constant(0)));
+                }
+                mem[target as usize] = value;
+                ip += 4;
+            },
+            99 => {
+                return mem[0];
+            },
+            _ => return None
+        }
+    }
+}
+
+// Nouns and verbs are both in 0..=99; the smallest noun wins, then the smallest verb.
+fn solve_affine(expr: &Affine, target: Word) -> Option<(Word, Word)> {
+    return (0..=99).filter_map(|noun| {
+        let rest = target.checked_sub(expr.constant)?.checked_sub(expr.noun.checked_mul(noun)?)?;
+        if expr.verb == 0 {
+            return if rest == 0 {Some((noun, 0))} else {None};
+        }
+        let verb = rest / expr.verb;
+        return if rest % expr.verb == 0 && (0..=99).contains(&verb) {Some((noun, verb))} else {None};
+    }).next();
+}
+
+fn brute_force(program: &[Word], target: Word) -> Option<(Word, Word)> {
+    return (0..100 * 100).into_par_iter()
+        .map(|i| (i / 100, i % 100))
+        .find_first(|&(noun, verb)| output(program, noun, verb) == target);
+}
+
+// Finds the noun and verb that produce `target`, solving symbolically where the program allows.
+fn solve(program: &[Word], target: Word) -> Option<(Word, Word)> {
+    return match run_symbolic(program) {
+        Some(expr) => solve_affine(&expr, target),
+        None => brute_force(program, target)
+    };
 }
 
 fn main() {
@@ -32,19 +278,16 @@ fn main() {
     let reader = BufReader::new(file);
     for line in reader.lines() {
         let line = line.expect("Failed to read");
-        let mut mem = parse(&line);
-
-        for noun in 0..=99 {
-            for verb in 0..=99 {
-                mem = parse(&line);
-                mem[1] = noun;
-                mem[2] = verb;
-                run(&mut mem);
-                if mem[0] == 19690720 {
-                    println!("{}", mem[0]);
-                    println!("Result {}", noun * 100 + verb);
-                }
-            }
+        let program = parse(&line);
+
+        println!("{}", output(&program, 12, 2));
+        match run_symbolic(&program) {
+            Some(expr) => println!("{} + {} * noun + {} * verb ({} at 12, 2)", expr.constant, expr.noun, expr.verb, expr.eval(12, 2)),
+            None => println!("Not affine, searching")
+        }
+        match solve(&program, 19690720) {
+            Some((noun, verb)) => println!("Result {}", noun * 100 + verb),
+            None => println!("No noun and verb give 19690720")
         }
     }
 }
@@ -56,9 +299,9 @@ mod tests {
 
     fn test_case(start: &str, expected: &str) {
         let expected = parse(expected);
-        let mut actual = parse(start);
-        run(&mut actual);
-        assert_eq!(actual, expected);
+        let mut actual = Machine::new(parse(start));
+        run(&mut actual, &mut vec![]);
+        assert_eq!(actual.mem, expected);
     }
 
     #[test]
@@ -68,4 +311,54 @@ mod tests {
         test_case("2,4,4,5,99,0 ", " 2,4,4,5,99,9801");
         test_case("1,1,1,4,99,5,6,0,99 ", " 30,1,1,4,2,5,6,0,99");
     }
+
+    #[test]
+    fn test_large_values() {
+        test_case("1102,3037000499,3037000499,5,99,0", "1102,3037000499,3037000499,5,99,9223372030926249001");
+        test_case("1101,-9223372036854775807,-1,5,99,0", "1101,-9223372036854775807,-1,5,99,-9223372036854775808");
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in multiply")]
+    fn test_multiply_overflow() {
+        run(&mut Machine::new(parse("1102,3037000500,3037000500,5,99,0")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in add")]
+    fn test_add_overflow() {
+        run(&mut Machine::new(parse("1101,9223372036854775807,1,5,99,0")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Negative Intcode address -1")]
+    fn test_negative_address() {
+        run(&mut Machine::new(parse("1,-1,0,0,99")), &mut vec![]);
+    }
+
+    #[test]
+    fn test_symbolic() {
+        assert_eq!(run_symbolic(&parse("1,0,0,0,1,1,2,0,99")), Some(Affine { constant: 0, noun: 1, verb: 1 }));
+        assert_eq!(run_symbolic(&parse("1,0,0,0,1002,1,3,0,1,0,2,0,99")), Some(Affine { constant: 0, noun: 3, verb: 1 }));
+        assert_eq!(solve(&parse("1,0,0,0,1,1,2,0,99"), 150), Some((51, 99)));
+        assert_eq!(solve(&parse("1,0,0,0,1,1,2,0,99"), 199), None);
+    }
+
+    #[test]
+    fn test_non_linear() {
+        let program = parse("1,0,0,0,2,1,2,0,99");
+        assert_eq!(run_symbolic(&program), None);
+        assert_eq!(solve(&program, 391), Some((17, 23)));
+    }
+
+    #[test]
+    fn test_real_program() {
+        let program = parse(std::fs::read_to_string("input").expect("Failed to open input").trim());
+        let expr = run_symbolic(&program).expect("Real program should be affine");
+        for &(noun, verb) in &[(12, 2), (0, 0), (99, 99), (64, 29)] {
+            assert_eq!(expr.eval(noun, verb), output(&program, noun, verb));
+        }
+        assert_eq!(solve(&program, 19690720), Some((64, 29)));
+        assert_eq!(solve(&program, 19690720), brute_force(&program, 19690720));
+    }
 }
@@ -1,3 +1,4 @@
+use std::fs;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
@@ -356,35 +357,663 @@ fn print_simplified_decisions(ds: &HashSet<Vec<Option<bool>>>) {
     }
 }
 
-fn main() {
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Mode {
+    Walk,
+    Run
+}
 
-    /*let states8 = passable_states(states(8));
+impl Mode {
+    fn sensors(self: &Mode) -> usize {
+        return match self {
+            Mode::Walk => 4,
+            Mode::Run => 9
+        };
+    }
 
-    let decisions = to_decisions(&solve_all(&states8), 4);
-    println!("{:?}", print_decisions(&decisions));
+    fn command(self: &Mode) -> &'static str {
+        return match self {
+            Mode::Walk => "WALK",
+            Mode::Run => "RUN"
+        };
+    }
+}
 
-    let ds = decision_set(&decisions);
+const MAX_INSTRUCTIONS: usize = 15;
 
-    print_simplified_decisions(&simplify(&ds, 0, 4));*/
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+enum Register {
+    Sensor(usize),
+    T,
+    J
+}
 
-    let states12 = passable_states(states(9, 9));
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            Register::Sensor(n) => write!(f, "{}", (b'A' + *n as u8) as char),
+            Register::T => write!(f, "T"),
+            Register::J => write!(f, "J")
+        };
+    }
+}
 
-    let decisions = to_decisions(&solve_all(&states12, 9), 9);
-    println!("{:?}", print_decisions(&decisions));
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+enum Instruction {
+    And(Register, Register),
+    Or(Register, Register),
+    Not(Register, Register)
+}
 
-    let ds = decision_set(&decisions);
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return match self {
+            Instruction::And(x, y) => write!(f, "AND {} {}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR {} {}", x, y),
+            Instruction::Not(x, y) => write!(f, "NOT {} {}", x, y)
+        };
+    }
+}
 
-    print_simplified_decisions(&simplify(&ds, 0, 9));
+fn to_springscript(program: &[Instruction], mode: Mode) -> String {
+    return program.iter().map(|i| format!("{}\n", i)).collect::<String>() + mode.command() + "\n";
+}
 
-    let file = File::open("input").expect("Failed to open input");
-    let reader = BufReader::new(file);
-    for line in reader.lines() {
-        let line = line.expect("Failed to read");
-        
-        {let mut machine = Machine::new(parse(&line));
+fn parse_register(name: &str) -> Result<Register, String> {
+    return match name {
+        "T" => Ok(Register::T),
+        "J" => Ok(Register::J),
+        _ if name.len() == 1 && ("A"..="I").contains(&name) => Ok(Register::Sensor((name.as_bytes()[0] - b'A') as usize)),
+        _ => Err(format!("Unknown register {}", name))
+    };
+}
+
+fn parse_springscript(script: &str) -> Result<(Vec<Instruction>, Mode), String> {
+    let mut program = vec![];
+    for line in script.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let mode = match words.as_slice() {
+            ["WALK"] => Some(Mode::Walk),
+            ["RUN"] => Some(Mode::Run),
+            _ => None
+        };
+        if let Some(mode) = mode {
+            if let Some(register) = program.iter().flat_map(|i| match i {
+                Instruction::And(x, _) | Instruction::Or(x, _) | Instruction::Not(x, _) => vec![*x]
+            }).find(|x| match x { Register::Sensor(n) => *n >= mode.sensors(), _ => false }) {
+                return Err(format!("{} can't use sensor {}", mode.command(), register));
+            }
+            return Ok((program, mode));
+        }
+        let (x, y) = match words.as_slice() {
+            [_, x, y] => (parse_register(x)?, parse_register(y)?),
+            _ => return Err(format!("Can't parse {}", line))
+        };
+        if let Register::Sensor(_) = y {
+            return Err(format!("Can't write to sensor {}", y));
+        }
+        program.push(match words[0] {
+            "AND" => Instruction::And(x, y),
+            "OR" => Instruction::Or(x, y),
+            "NOT" => Instruction::Not(x, y),
+            _ => return Err(format!("Unknown instruction {}", words[0]))
+        });
+        if program.len() > MAX_INSTRUCTIONS {
+            return Err(format!("Only {} instructions fit", MAX_INSTRUCTIONS));
+        }
+    }
+    return Err("Missing WALK or RUN".to_string());
+}
+
+// Runs a program against the sensors, returning whether the droid jumps.
+fn execute(program: &[Instruction], sensors: &[bool]) -> bool {
+    let mut t = false;
+    let mut j = false;
+    for instruction in program {
+        let (x, y) = match instruction {
+            Instruction::And(x, y) | Instruction::Or(x, y) | Instruction::Not(x, y) => (x, y)
+        };
+        let x = match x {
+            Register::Sensor(n) => sensors[*n],
+            Register::T => t,
+            Register::J => j
+        };
+        let y = match y {
+            Register::T => &mut t,
+            Register::J => &mut j,
+            Register::Sensor(_) => panic!("Can't write to a sensor")
+        };
+        *y = match instruction {
+            Instruction::And(_, _) => x && *y,
+            Instruction::Or(_, _) => x || *y,
+            Instruction::Not(_, _) => !x
+        };
+    }
+    return j;
+}
+
+// A formula in negation normal form: negations only appear on sensors.
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Formula {
+    Sensor(usize, bool),
+    And(Vec<Formula>),
+    Or(Vec<Formula>)
+}
+
+impl std::fmt::Display for Formula {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn join(f: &mut std::fmt::Formatter, terms: &[Formula], op: &str) -> std::fmt::Result {
+            for (i, term) in terms.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", op)?;
+                }
+                match term {
+                    Formula::Sensor(_, _) => write!(f, "{}", term)?,
+                    _ => write!(f, "({})", term)?
+                }
+            }
+            return Ok(());
+        }
+        return match self {
+            Formula::Sensor(n, true) => write!(f, "{}", Register::Sensor(*n)),
+            Formula::Sensor(n, false) => write!(f, "!{}", Register::Sensor(*n)),
+            Formula::And(terms) => join(f, terms, "&"),
+            Formula::Or(terms) => join(f, terms, "|")
+        };
+    }
+}
+
+impl Formula {
+    fn and(terms: Vec<Formula>) -> Formula {
+        return Formula::And(terms.into_iter().flat_map(|term| match term {
+            Formula::And(inner) => inner,
+            _ => vec![term]
+        }).collect());
+    }
+
+    fn or(terms: Vec<Formula>) -> Formula {
+        return Formula::Or(terms.into_iter().flat_map(|term| match term {
+            Formula::Or(inner) => inner,
+            _ => vec![term]
+        }).collect());
+    }
+
+    fn negate(self: &Formula) -> Formula {
+        return match self {
+            Formula::Sensor(n, positive) => Formula::Sensor(*n, !positive),
+            Formula::And(terms) => Formula::or(terms.iter().map(|t| t.negate()).collect()),
+            Formula::Or(terms) => Formula::and(terms.iter().map(|t| t.negate()).collect())
+        };
+    }
+
+    fn eval(self: &Formula, sensors: &[bool]) -> bool {
+        return match self {
+            Formula::Sensor(n, positive) => sensors[*n] == *positive,
+            Formula::And(terms) => terms.iter().all(|t| t.eval(sensors)),
+            Formula::Or(terms) => terms.iter().any(|t| t.eval(sensors))
+        };
+    }
+
+    fn sensors(self: &Formula) -> usize {
+        return match self {
+            Formula::Sensor(n, _) => n + 1,
+            Formula::And(terms) | Formula::Or(terms) => terms.iter().map(|t| t.sensors()).max().unwrap_or(0)
+        };
+    }
+}
+
+// Parses formulas like `J = (!A | !B | !C) & D`, where & binds tighter than |.
+fn parse_formula(input: &str) -> Result<Formula, String> {
+    fn expect(tokens: &[char], pos: &mut usize, what: char) -> Result<(), String> {
+        if tokens.get(*pos) != Some(&what) {
+            return Err(format!("Expected '{}' at {}", what, *pos));
+        }
+        *pos += 1;
+        return Ok(());
+    }
+
+    fn or(tokens: &[char], pos: &mut usize) -> Result<Formula, String> {
+        let mut terms = vec![and(tokens, pos)?];
+        while tokens.get(*pos) == Some(&'|') {
+            *pos += 1;
+            terms.push(and(tokens, pos)?);
+        }
+        return Ok(if terms.len() == 1 {terms.pop().unwrap()} else {Formula::or(terms)});
+    }
 
-        println!("{}", to_ascii(&run(&mut machine, &mut vec![])));
+    fn and(tokens: &[char], pos: &mut usize) -> Result<Formula, String> {
+        let mut terms = vec![unary(tokens, pos)?];
+        while tokens.get(*pos) == Some(&'&') {
+            *pos += 1;
+            terms.push(unary(tokens, pos)?);
+        }
+        return Ok(if terms.len() == 1 {terms.pop().unwrap()} else {Formula::and(terms)});
+    }
+
+    fn unary(tokens: &[char], pos: &mut usize) -> Result<Formula, String> {
+        *pos += 1;
+        return match tokens.get(*pos - 1) {
+            Some('!') => Ok(unary(tokens, pos)?.negate()),
+            Some('(') => {
+                let inner = or(tokens, pos)?;
+                expect(tokens, pos, ')')?;
+                Ok(inner)
+            },
+            Some(c) if ('A'..='I').contains(c) => Ok(Formula::Sensor((*c as u8 - b'A') as usize, true)),
+            Some(c) => Err(format!("Unexpected '{}' at {}", c, *pos - 1)),
+            None => Err("Unexpected end of formula".to_string())
+        };
+    }
+
+    let mut tokens: Vec<char> = input.chars().filter(|c| !c.is_whitespace()).collect();
+    if tokens.starts_with(&['J', '=']) {
+        tokens.drain(0..2);
+    }
+    let mut pos = 0;
+    let formula = or(&tokens, &mut pos)?;
+    if pos < tokens.len() {
+        return Err(format!("Unexpected '{}' at {}", tokens[pos], pos));
+    }
+    return Ok(formula);
+}
+
+fn writes(code: &[Instruction], register: Register) -> bool {
+    return code.iter().any(|i| match i {
+        Instruction::And(_, y) | Instruction::Or(_, y) | Instruction::Not(_, y) => *y == register
+    });
+}
+
+fn shortest(a: Option<Vec<Instruction>>, b: Option<Vec<Instruction>>) -> Option<Vec<Instruction>> {
+    return match (a, b) {
+        (Some(a), Some(b)) => Some(if b.len() < a.len() {b} else {a}),
+        (a, b) => a.or(b)
+    };
+}
+
+type CompileCache = HashMap<(String, Register, Option<Register>, (bool, bool)), Option<Vec<Instruction>>>;
+
+// Computes the formula into `target`, optionally using `scratch`. A fresh register is
+// still false, so a sensor can be loaded into it with a single OR.
+fn compile_into(formula: &Formula, target: Register, scratch: Option<Register>, fresh: (bool, bool), cache: &mut CompileCache) -> Option<Vec<Instruction>> {
+    let key = (formula.to_string(), target, scratch, fresh);
+    if let Some(code) = cache.get(&key) {
+        return code.clone();
+    }
+    let direct = compile_direct(formula, target, scratch, fresh, cache);
+    let inverted = compile_direct(&formula.negate(), target, scratch, fresh, cache).map(|mut code| {
+        code.push(Instruction::Not(target, target));
+        code
+    });
+    let code = shortest(direct, inverted);
+    cache.insert(key, code.clone());
+    return code;
+}
+
+fn compile_direct(formula: &Formula, target: Register, scratch: Option<Register>, fresh: (bool, bool), cache: &mut CompileCache) -> Option<Vec<Instruction>> {
+    let (terms, is_and) = match formula {
+        Formula::Sensor(n, true) => return if fresh.0 {Some(vec![Instruction::Or(Register::Sensor(*n), target)])} else {None},
+        Formula::Sensor(n, false) => return Some(vec![Instruction::Not(Register::Sensor(*n), target)]),
+        Formula::And(terms) => (terms, true),
+        Formula::Or(terms) => (terms, false)
+    };
+    let combine = |x| if is_and {Instruction::And(x, target)} else {Instruction::Or(x, target)};
+
+    // Try computing each term first, then fold the rest in: subformulas need the scratch
+    // register to themselves, then negated sensors, then plain sensors need nothing.
+    let mut best = None;
+    for first in 0..terms.len() {
+        let mut code = match compile_into(&terms[first], target, scratch, fresh, cache) {
+            Some(code) => code,
+            None => continue
+        };
+        let mut rest: Vec<&Formula> = terms.iter().enumerate().filter(|(i, _)| *i != first).map(|(_, t)| t).collect();
+        rest.sort_by_key(|t| match t {
+            Formula::Sensor(_, true) => 2,
+            Formula::Sensor(_, false) => 1,
+            _ => 0
+        });
+        let mut scratch_fresh = fresh.1 && !scratch.iter().any(|s| writes(&code, *s));
+        let mut possible = true;
+        for term in rest {
+            match (term, scratch) {
+                (Formula::Sensor(n, true), _) => code.push(combine(Register::Sensor(*n))),
+                (_, Some(s)) => match compile_into(term, s, None, (scratch_fresh, false), cache) {
+                    Some(mut sub) => {
+                        code.append(&mut sub);
+                        code.push(combine(s));
+                        scratch_fresh = false;
+                    },
+                    None => {
+                        possible = false;
+                        break;
+                    }
+                },
+                (_, None) => {
+                    possible = false;
+                    break;
+                }
+            }
+        }
+        if possible {
+            best = shortest(best, Some(code));
+        }
+    }
+    return best;
+}
+
+// Compiles a formula to springscript that leaves the answer in J.
+fn compile(formula: &Formula, mode: Mode) -> Result<Vec<Instruction>, String> {
+    if formula.sensors() > mode.sensors() {
+        return Err(format!("{} can only see {} tiles ahead", mode.command(), mode.sensors()));
+    }
+    let code = match compile_into(formula, Register::J, Some(Register::T), (true, true), &mut HashMap::new()) {
+        Some(code) => code,
+        None => return Err(format!("{} needs more than two registers", formula))
+    };
+    if code.len() > MAX_INSTRUCTIONS {
+        return Err(format!("{} needs {} instructions, only {} fit", formula, code.len(), MAX_INSTRUCTIONS));
+    }
+    return Ok(code);
+}
+
+fn covers(implicant: &[Option<bool>], state: &[bool]) -> bool {
+    return implicant.iter().zip(state.iter()).all(|(i, s)| *i != Some(!*s));
+}
+
+// Quine-McCluskey: repeatedly merge implicants differing in one position, keeping
+// whichever never merged as prime implicants.
+fn prime_implicants(minterms: &[Vec<bool>]) -> Vec<Vec<Option<bool>>> {
+    let mut current: HashSet<Vec<Option<bool>>> = minterms.iter().map(|m| m.iter().map(|b| Some(*b)).collect()).collect();
+    let mut primes = vec![];
+    while !current.is_empty() {
+        let mut merged = HashSet::new();
+        let mut used = HashSet::new();
+        for a in current.iter() {
+            for i in 0..a.len() {
+                if let Some(bit) = a[i] {
+                    let mut b = a.clone();
+                    b[i] = Some(!bit);
+                    if current.contains(&b) {
+                        b[i] = None;
+                        merged.insert(b);
+                        used.insert(a.clone());
+                    }
+                }
+            }
+        }
+        primes.extend(current.into_iter().filter(|a| !used.contains(a)));
+        current = merged;
+    }
+    primes.sort();
+    return primes;
+}
+
+fn literal_count(cover: &[&Vec<Option<bool>>]) -> usize {
+    return cover.iter().map(|i| i.iter().filter(|b| b.is_some()).count()).sum();
+}
+
+// Finds the cover of the minterms with the fewest implicants, then fewest literals.
+fn minimum_cover<'a>(primes: &'a [Vec<Option<bool>>], minterms: &[Vec<bool>]) -> Vec<&'a Vec<Option<bool>>> {
+    fn search<'a>(primes: &'a [Vec<Option<bool>>], uncovered: &[&Vec<bool>], chosen: &mut Vec<&'a Vec<Option<bool>>>, best: &mut Option<Vec<&'a Vec<Option<bool>>>>) {
+        if let Some(best) = best {
+            if chosen.len() > best.len() || (chosen.len() == best.len() && literal_count(chosen) >= literal_count(best)) {
+                return;
+            }
+        }
+        let first = match uncovered.first() {
+            Some(first) => first,
+            None => {
+                *best = Some(chosen.clone());
+                return;
+            }
+        };
+        // Some implicant has to cover the first uncovered minterm
+        for prime in primes.iter().filter(|p| covers(p, first)) {
+            let remaining: Vec<&Vec<bool>> = uncovered.iter().filter(|m| !covers(prime, m)).cloned().collect();
+            chosen.push(prime);
+            search(primes, &remaining, chosen, best);
+            chosen.pop();
+        }
+    }
+
+    let mut best = None;
+    search(primes, &minterms.iter().collect::<Vec<&Vec<bool>>>(), &mut vec![], &mut best);
+    return best.unwrap_or_default();
+}
+
+type Product = Vec<(usize, bool)>;
+
+// Turns a sum of products into a formula, factoring out the literal shared by the most
+// products so the springscript can test it once.
+fn factor(products: &[Product]) -> Formula {
+    let mut counts: HashMap<(usize, bool), usize> = HashMap::new();
+    for product in products {
+        for literal in product {
+            *counts.entry(*literal).or_insert(0) += 1;
+        }
+    }
+    let common = counts.iter().filter(|(_, count)| **count > 1).max_by_key(|(literal, count)| (**count, std::cmp::Reverse(**literal))).map(|(literal, _)| *literal);
+    if let Some(common) = common {
+        let (with, without): (Vec<Product>, Vec<Product>) = products.iter().cloned().partition(|p| p.contains(&common));
+        let remainders: Vec<Product> = with.into_iter().map(|p| p.into_iter().filter(|l| *l != common).collect()).collect();
+        let shared = Formula::Sensor(common.0, common.1);
+        let factored = if remainders.iter().any(|r| r.is_empty()) {
+            shared
+        } else {
+            Formula::and(vec![shared, factor(&remainders)])
+        };
+        return if without.is_empty() {factored} else {Formula::or(vec![factored, factor(&without)])};
+    }
+    let terms: Vec<Formula> = products.iter().map(|p| {
+        let literals: Vec<Formula> = p.iter().map(|(n, positive)| Formula::Sensor(*n, *positive)).collect();
+        if literals.len() == 1 {literals[0].clone()} else {Formula::and(literals)}
+    }).collect();
+    return if terms.len() == 1 {terms[0].clone()} else {Formula::or(terms)};
+}
+
+// Walks the droid across the hull, jumping whenever `jumps` says so for what it can see.
+// Returns the position of the hole it falls into if it doesn't make it.
+fn cross(hull: &[bool], sight: usize, jumps: &dyn Fn(&[bool]) -> bool) -> Result<(), usize> {
+    let mut pos = 0;
+    while pos + sight < hull.len() {
+        let lands = if jumps(&hull[pos..(pos + sight)]) {pos + 3} else {pos};
+        if !hull[lands] {
+            return Err(lands);
+        }
+        pos = lands + 1;
+    }
+    return Ok(());
+}
+
+// Springdroid simulator: runs the program over a hull drawn as in the droid's output, where
+// the first tile is the one it starts on. Returns the x of the hole it falls into, if any.
+fn simulate(program: &[Instruction], mode: Mode, hull: &str) -> Result<(), usize> {
+    return cross(&parse_hull(hull, mode.sensors()), mode.sensors(), &|view| execute(program, view)).map_err(|x| x + 1);
+}
 
+// Finds a way across the hull that sticks to the fixed choices, preferring to do what
+// `prefer` says elsewhere. When the droid falls we backtrack to the most recent view
+// that hasn't tried the other choice yet. Returns the choices made for new views.
+fn find_path(hull: &[bool], sight: usize, fixed: &HashMap<Vec<bool>, bool>, prefer: &dyn Fn(&[bool]) -> bool) -> Option<HashMap<Vec<bool>, bool>> {
+    let mut choices: HashMap<Vec<bool>, bool> = HashMap::new();
+    let mut trail: Vec<(Vec<bool>, usize)> = vec![];
+    let mut pos = 0;
+    while pos + sight < hull.len() {
+        let view = &hull[pos..(pos + sight)];
+        let jump = match fixed.get(view).or_else(|| choices.get(view)) {
+            Some(jump) => *jump,
+            None => {
+                choices.insert(view.to_vec(), prefer(view));
+                trail.push((view.to_vec(), pos));
+                prefer(view)
+            }
+        };
+        let lands = if jump {pos + 3} else {pos};
+        if hull[lands] {
+            pos = lands + 1;
+            continue;
+        }
+        loop {
+            let (view, p) = trail.pop()?;
+            if choices[&view] == prefer(&view) {
+                choices.insert(view.clone(), !prefer(&view));
+                trail.push((view, p));
+                pos = p;
+                break;
+            }
+            choices.remove(&view);
+        }
+    }
+    return Some(choices);
+}
+
+// The smallest formula that jumps and walks where required, treating the rest as don't cares.
+fn minimise(required: &HashMap<Vec<bool>, bool>, sight: usize) -> Formula {
+    let mut on: Vec<Vec<bool>> = required.iter().filter(|(_, jump)| **jump).map(|(view, _)| view.clone()).collect();
+    on.sort();
+    let allowed: Vec<Vec<bool>> = states(sight, 0).into_iter().filter(|v| required.get(v) != Some(&false)).collect();
+    let primes = prime_implicants(&allowed);
+    let cover = minimum_cover(&primes, &on);
+    let products: Vec<Product> = cover.iter().map(|i| i.iter().enumerate().filter_map(|(n, b)| b.map(|b| (n, b))).collect()).collect();
+    return factor(&products);
+}
+
+// A springscript program along with the formula it came from and the hulls it gave up on,
+// because getting across them would drop the droid down others.
+struct Synthesis {
+    formula: Formula,
+    program: Vec<Instruction>,
+    skipped: Vec<Vec<bool>>
+}
+
+// Settles on the simplest formula that sticks to the required choices and gets the droid
+// across every hull it can: whenever the formula drops the droid down a hole, find a way
+// across that hull and require the choices the formula got wrong, or give up on the hull if
+// there isn't one. Returns the formula and the hulls it still falls down.
+fn settle(hulls: &[Vec<bool>], sight: usize, mut required: HashMap<Vec<bool>, bool>) -> (Formula, Vec<usize>) {
+    let mut skipped = vec![false; hulls.len()];
+    loop {
+        let formula = minimise(&required, sight);
+        let jumps = |view: &[bool]| formula.eval(view);
+        let failing = (0..hulls.len()).find(|i| !skipped[*i] && cross(&hulls[*i], sight, &jumps).is_err());
+        let failing = match failing {
+            Some(failing) => failing,
+            None => {
+                let falls = (0..hulls.len()).filter(|i| skipped[*i] && cross(&hulls[*i], sight, &jumps).is_err()).collect();
+                return (formula, falls);
+            }
+        };
+        match find_path(&hulls[failing], sight, &required, &jumps) {
+            Some(choices) => required.extend(choices.into_iter().filter(|(view, jump)| formula.eval(view) != *jump)),
+            None => skipped[failing] = true
+        }
+    }
+}
+
+// Searches for the simplest formula that gets the droid across as many hulls as it can,
+// starting from the views every way across jumps from.
+fn synthesize(mode: Mode, length: usize, seen: &[Vec<bool>]) -> Result<Synthesis, String> {
+    let sight = mode.sensors();
+    let passable = passable_states(states(length, sight));
+    let solutions = solve_all(&passable, sight);
+    let hulls: Vec<Vec<bool>> = solutions.iter().map(|(h, _)| h.to_vec()).collect();
+
+    let mut required: HashMap<Vec<bool>, bool> = to_decisions(&solutions, sight).into_iter()
+        .filter(|(_, (walk, _))| *walk == 0)
+        .map(|(view, _)| (view, true))
+        .collect();
+    // Jumping with a hole at D always lands in it
+    for view in states(sight, 0) {
+        if view[0] && !view[3] {
+            required.insert(view, false);
+        }
+    }
+    // Pin down every choice on the hulls the real droid has shown us, so they stay crossed
+    for hull in seen {
+        let formula = minimise(&required, sight);
+        match find_path(hull, sight, &required, &|view| formula.eval(view)) {
+            Some(choices) => required.extend(choices),
+            None => return Err(format!("No way to {} across {}", mode.command(), state_to_string(hull)))
+        }
+    }
+    let (mut formula, mut skipped) = settle(&hulls, sight, required.clone());
+    // Settling a hull at a time can paint the formula into a corner, so see whether
+    // starting from a way across one of the hulls it gave up on loses fewer
+    let mut i = 0;
+    while i < skipped.len() {
+        let restart = find_path(&hulls[skipped[i]], sight, &required, &|view| formula.eval(view)).map(|choices| {
+            let mut restart = required.clone();
+            restart.extend(choices);
+            settle(&hulls, sight, restart)
+        });
+        match restart {
+            Some((better, fewer)) if fewer.len() < skipped.len() => {
+                formula = better;
+                skipped = fewer;
+                i = 0;
+            },
+            _ => i += 1
+        }
+    }
+    let program = compile(&formula, mode)?;
+    let skipped = skipped.into_iter().map(|i| hulls[i].clone()).collect();
+    return Ok(Synthesis { formula, program, skipped });
+}
+
+// Picks the hull out of the droid's death animation.
+fn hull_row(fall: &str) -> Option<&str> {
+    return fall.lines().rev().find(|l| l.contains('#') && l.chars().all(|c| c == '#' || c == '.'));
+}
+
+// Drops the tile the droid starts on and pads the end with ground it can see.
+fn parse_hull(row: &str, sight: usize) -> Vec<bool> {
+    let mut hull = to_bools(&row[1..]);
+    hull.resize(hull.len() + sight, true);
+    return hull;
+}
+
+// Draws a hull as the droid does, starting with the tile it stands on.
+fn draw_hull(hull: &[bool]) -> String {
+    return "#".to_string() + &state_to_string(&hull.to_vec()).replace(' ', ".");
+}
+
+fn to_bools(x: &str) -> Vec<bool> {
+    x.as_bytes().iter().map(|c| *c == b'#').collect()
+}
+
+// Long enough to include every way of combining a few holes
+const HULL_LENGTH: usize = 10;
+
+// Keeps synthesizing until the real droid makes it, learning from each hull it falls down.
+// Returns the synthesis and the hull damage.
+fn synthesize_for(line: &str, mode: Mode) -> Result<(Synthesis, Word), String> {
+    let mut seen = vec![];
+    loop {
+        let synthesis = synthesize(mode, HULL_LENGTH, &seen)?;
+        match survey(line, &synthesis.program, mode) {
+            Ok(damage) => return Ok((synthesis, damage)),
+            Err(fall) => match hull_row(&fall) {
+                Some(row) if !seen.contains(&parse_hull(row, mode.sensors())) => seen.push(parse_hull(row, mode.sensors())),
+                _ => return Err(fall)
+            }
+        }
+    }
+}
+
+// Runs the program on the real droid, returning the hull damage or the ASCII of its fall.
+fn survey(line: &str, program: &[Instruction], mode: Mode) -> Result<Word, String> {
+    let mut machine = Machine::new(parse(line));
+    run(&mut machine, &mut vec![]);
+    let output = run(&mut machine, &mut from_ascii(&to_springscript(program, mode)));
+    return match output.last() {
+        Some(damage) if *damage > 127 => Ok(*damage),
+        _ => Err(to_ascii(&output))
+    };
+}
+
+// Working it out by hand, before the synthesizer:
+//
+// WALK
 // !##.#.
 // !.....
 //  ABCD
@@ -400,7 +1029,7 @@ aD
 ABcD
 AbD
 */
-        let mut prog = from_ascii(r"NOT A J
+/*        let mut prog = from_ascii(r"NOT A J
 AND D J
 NOT C T
 AND B T
@@ -410,17 +1039,9 @@ NOT B T
 AND D T
 OR T J
 WALK
-");
-
-        let mut output = run(&mut machine, &mut prog);
-
-        println!("{}", to_ascii(&output));
-        println!("Result: {}", output.pop().unwrap());
-        }
-        {let mut machine = Machine::new(parse(&line));
-
-        println!("{}", to_ascii(&run(&mut machine, &mut vec![])));
+");*/
 
+// RUN
 // !##.#.    // covered
 // !##.v#.#v // covered
 // !#.##...#
@@ -529,7 +1150,7 @@ NOT A T
 OR T J
 */
 
-        let mut prog = from_ascii(
+/*        let mut prog = from_ascii(
 r"NOT B T
 NOT C J
 OR T J
@@ -538,7 +1159,7 @@ AND H J
 NOT A T
 OR T J
 RUN
-");
+");*/
 
 /*
 Sunday afternoon, seeing that you can simplify the following pairs
@@ -599,10 +1220,82 @@ OR T J
 Since we can use the insight that if H is true in all of the jump cases, then we can ignore E and F and just jump to H. If H is not available, we can jump to E directly.
 */
 
-        let mut output = run(&mut machine, &mut prog);
+// Prints the synthesized program for each mode and what the droid made of it.
+fn report(line: &str) {
+    for mode in [Mode::Walk, Mode::Run].iter() {
+        match synthesize_for(line, *mode) {
+            Ok((synthesis, damage)) => {
+                println!("Gave up on {} hulls {} tiles long that clash with the rest", synthesis.skipped.len(), HULL_LENGTH);
+                println!("J = {}", synthesis.formula);
+                print!("{}", to_springscript(&synthesis.program, *mode));
+                println!("Result: {}", damage);
+            },
+            Err(fall) => println!("{}", fall)
+        }
+    }
+
+}
+
+// Tries a springscript program on every passable hull, then on the real droid.
+fn check(line: &str, script: &str) {
+    let (program, mode) = match parse_springscript(script) {
+        Ok(parsed) => parsed,
+        Err(error) => return println!("{}", error)
+    };
+    let sight = mode.sensors();
+    let passable = passable_states(states(HULL_LENGTH, sight));
+    let hulls = solve_all(&passable, sight);
+    let mut crossed = 0;
+    for (hull, _) in hulls.iter() {
+        let drawn = draw_hull(hull);
+        match simulate(&program, mode, &drawn) {
+            Ok(()) => crossed += 1,
+            Err(x) => println!("Falls down the hole at {} in {}", x, drawn)
+        }
+    }
+    println!("Crossed {} of {} hulls", crossed, hulls.len());
+    match survey(line, &program, mode) {
+        Ok(damage) => println!("Result: {}", damage),
+        Err(fall) => println!("{}", fall)
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mode = if args.iter().any(|arg| arg == "run") {Mode::Run} else {Mode::Walk};
+    match args.get(1).map(|arg| arg.as_str()) {
+        // `cargo run -- compile "J = (!A | !B | !C) & D"` prints the springscript for a formula
+        Some("compile") => {
+            let formula = match parse_formula(args.get(2).expect("Which formula?")) {
+                Ok(formula) => formula,
+                Err(error) => return println!("{}", error)
+            };
+            let mode = if formula.sensors() > Mode::Walk.sensors() {Mode::Run} else {Mode::Walk};
+            match compile(&formula, mode) {
+                Ok(program) => print!("{}", to_springscript(&program, mode)),
+                Err(error) => println!("{}", error)
+            }
+            return;
+        },
+        // `cargo run -- decisions [run]` prints which views the ways across jump or walk from
+        Some("decisions") => {
+            let sight = mode.sensors();
+            let passable = passable_states(states(HULL_LENGTH, sight));
+            let decisions = to_decisions(&solve_all(&passable, sight), sight);
+            print_decisions(&decisions);
+            print_simplified_decisions(&simplify(&decision_set(&decisions), 0, sight));
+            return;
+        },
+        _ => {}
+    }
 
-        println!("{}", to_ascii(&output));
-        println!("Result: {}", output.pop().unwrap());
+    let file = File::open("input").expect("Failed to open input");
+    for line in BufReader::new(file).lines() {
+        let line = line.expect("Failed to read");
+        match args.get(1).map(|arg| arg.as_str()) {
+            // `cargo run -- check <file>` tries a springscript program offline and on the droid
+            Some("check") => check(&line, &fs::read_to_string(args.get(2).expect("Which file?")).expect("Couldn't read program")),
+            _ => report(&line)
         }
     }
 }
@@ -620,10 +1313,6 @@ mod tests {
         assert_eq!(output, expected);
     }
 
-    fn to_bools(x: &str) -> Vec<bool> {
-        x.as_bytes().iter().map(|c| *c == '#' as u8).collect()
-    }
-    
     #[test]
     fn test_simplify1() {
         let mut a1 = HashSet::new();
@@ -649,7 +1338,7 @@ mod tests {
     #[test]
     fn test_solve() {
         //                         0123456789012 
-        assert_eq!(solve(&to_bools("##   #  ####"), 0), vec![[2, 6]]);
+        assert_eq!(solve(&to_bools("##   #  ####"), 0, 4), vec![[2, 6]]);
     }
 
     #[test]
@@ -707,4 +1396,156 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    fn check_compiles(formula: &str, mode: Mode, expected_length: usize) {
+        let formula = parse_formula(formula).unwrap();
+        let program = compile(&formula, mode).unwrap();
+        for sensors in states(mode.sensors(), 0) {
+            assert_eq!(execute(&program, &sensors), formula.eval(&sensors), "{} with {}", formula, state_to_string(&sensors));
+        }
+        assert_eq!(program.len(), expected_length, "{}", to_springscript(&program, mode).replace("\n", "; "));
+    }
+
+    #[test]
+    fn test_parse_formula() {
+        assert_eq!(parse_formula("J = !A | B & C").unwrap().to_string(), "!A | (B & C)");
+        assert_eq!(parse_formula("!(A | !B)").unwrap(), Formula::And(vec![Formula::Sensor(0, false), Formula::Sensor(1, true)]));
+        assert_eq!(parse_formula("A & (B | C"), Err("Expected ')' at 6".to_string()));
+        assert_eq!(parse_formula("A & X"), Err("Unexpected 'X' at 2".to_string()));
+    }
+
+    #[test]
+    fn test_compile() {
+        check_compiles("D", Mode::Walk, 1);
+        check_compiles("!A", Mode::Walk, 1);
+        check_compiles("A & B", Mode::Walk, 2);
+        check_compiles("(!A | !B | !C) & D", Mode::Walk, 5);
+        check_compiles("J = (!B | !C) & D & H | !A", Mode::Run, 7);
+        check_compiles("(!A | !B | !C) & D & (E | H)", Mode::Run, 8);
+        check_compiles("(A & B) | (C & D) | (E & F)", Mode::Run, 9);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(compile(&parse_formula("E").unwrap(), Mode::Walk), Err("WALK can only see 4 tiles ahead".to_string()));
+        assert!(compile(&parse_formula("(A | B) & (C | D) | (E | F) & (G | H)").unwrap(), Mode::Run).is_err());
+        assert_eq!(
+            compile(&parse_formula("A & !B | !C & D | E & !F | !G & H | I & !A | B & !D").unwrap(), Mode::Run),
+            Err("(A & !B) | (!C & D) | (E & !F) | (!G & H) | (I & !A) | (B & !D) needs 17 instructions, only 15 fit".to_string()));
+    }
+
+    #[test]
+    fn test_prime_implicants() {
+        let minterms: Vec<Vec<bool>> = ["000", "001", "011", "111"].iter().map(|m| m.chars().map(|c| c == '1').collect()).collect();
+        let primes = prime_implicants(&minterms);
+        assert_eq!(primes, vec![
+            vec![None, Some(true), Some(true)],
+            vec![Some(false), None, Some(true)],
+            vec![Some(false), Some(false), None]
+        ]);
+        let cover = minimum_cover(&primes, &minterms);
+        assert_eq!(cover, vec![&vec![Some(false), Some(false), None], &vec![None, Some(true), Some(true)]]);
+    }
+
+    #[test]
+    fn test_factor() {
+        let products = vec![vec![(1, false), (3, true), (7, true)], vec![(2, false), (3, true), (7, true)], vec![(0, false)]];
+        assert_eq!(factor(&products).to_string(), "(D & H & (!B | !C)) | !A");
+    }
+
+    #[test]
+    fn test_cross() {
+        let hull = to_bools("##..#.#####");
+        assert_eq!(cross(&hull, 4, &|view| !view[0]), Err(5));
+        assert_eq!(cross(&hull, 4, &|view| (!view[0] || !view[2]) && view[3]), Ok(()));
+    }
+
+    #[test]
+    fn test_synthesize() {
+        let seen = vec![parse_hull(hull_row("@\n#####.#..########\n").unwrap(), 4)];
+        let synthesis = synthesize(Mode::Walk, 8, &seen).unwrap();
+        assert_eq!(synthesis.formula.to_string(), "(D & (!C | !B)) | !A");
+        assert_eq!(synthesis.program.len(), 6);
+        assert_eq!(cross(&seen[0], 4, &|view| execute(&synthesis.program, view)), Ok(()));
+    }
+
+    #[test]
+    fn test_parse_springscript() {
+        let (program, mode) = parse_springscript("NOT A J\nAND D J\nWALK\n").unwrap();
+        assert_eq!(mode, Mode::Walk);
+        assert_eq!(program, vec![Instruction::Not(Register::Sensor(0), Register::J), Instruction::And(Register::Sensor(3), Register::J)]);
+        assert_eq!(parse_springscript("OR E J\nWALK\n"), Err("WALK can't use sensor E".to_string()));
+        assert_eq!(parse_springscript("OR A B\nRUN\n"), Err("Can't write to sensor B".to_string()));
+        assert_eq!(parse_springscript("XOR A J\nRUN\n"), Err("Unknown instruction XOR".to_string()));
+        assert_eq!(parse_springscript("OR A J\n"), Err("Missing WALK or RUN".to_string()));
+    }
+
+    #[test]
+    fn test_simulate() {
+        let (program, mode) = parse_springscript("NOT A J\nWALK\n").unwrap();
+        assert_eq!(simulate(&program, mode, "#####.#..########"), Err(8));
+        assert_eq!(simulate(&program, mode, "#####...#########"), Ok(()));
+        let (program, mode) = parse_springscript("NOT A J\nNOT B T\nOR T J\nNOT C T\nOR T J\nAND D J\nWALK\n").unwrap();
+        assert_eq!(simulate(&program, mode, "#####.#..########"), Ok(()));
+        assert_eq!(simulate(&program, mode, "#####.#.#.#..####"), Ok(()));
+        assert_eq!(simulate(&program, mode, "#####.#.##..#####"), Err(7));
+    }
+
+    // Checks the synthesized program on every passable hull, returning the hulls it falls down.
+    fn check_synthesized(mode: Mode, length: usize) -> (Synthesis, Vec<Vec<bool>>) {
+        let synthesis = synthesize(mode, length, &[]).unwrap();
+        let mut failed = vec![];
+        for hull in passable_states(states(length, mode.sensors())) {
+            let drawn = draw_hull(&hull);
+            let result = simulate(&synthesis.program, mode, &drawn);
+            // The springscript must behave exactly as the formula it came from
+            assert_eq!(result, cross(&hull, mode.sensors(), &|view| synthesis.formula.eval(view)).map_err(|x| x + 1));
+            let possible = !solve(&hull, 0, mode.sensors()).is_empty();
+            match result {
+                Ok(()) => assert!(possible, "{} crossed an impossible hull", drawn),
+                Err(x) => {
+                    assert!(!hull[x - 1], "{} fell at {} which isn't a hole", drawn, x);
+                    if possible {
+                        failed.push(hull);
+                    }
+                }
+            }
+        }
+        return (synthesis, failed);
+    }
+
+    #[test]
+    fn test_synthesized_walk() {
+        let (synthesis, failed) = check_synthesized(Mode::Walk, 10);
+        assert!(!failed.is_empty());
+        for hull in &failed {
+            assert!(synthesis.skipped.contains(hull), "{} fell down {} without skipping it", synthesis.formula, draw_hull(hull));
+        }
+        // With a hole at A the droid has to jump and with one at D it has to walk, leaving only
+        // the four views with ground at both to choose for. Whichever choices get across a
+        // skipped hull must drop the droid down one the synthesized program gets across.
+        let passable = passable_states(states(10, 4));
+        let possible: Vec<&Vec<bool>> = solve_all(&passable, 4).into_iter().map(|(hull, _)| hull).collect();
+        for choices in 0..16 {
+            let jumps = |view: &[bool]| !view[0] || (view[3] && choices & (1 << (view[1] as usize + 2 * view[2] as usize)) != 0);
+            let falls: Vec<&Vec<bool>> = possible.iter().filter(|hull| cross(hull, 4, &jumps).is_err()).cloned().collect();
+            assert!(falls.len() >= failed.len());
+            if synthesis.skipped.iter().any(|hull| !falls.contains(&hull)) {
+                assert!(falls.iter().any(|hull| !failed.contains(hull)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_synthesized_run() {
+        let (synthesis, failed) = check_synthesized(Mode::Run, 10);
+        assert!(failed.is_empty(), "{} fell down {} hulls", synthesis.formula, failed.len());
+        assert!(synthesis.skipped.is_empty());
+    }
+
+    #[test]
+    fn test_draw_hull() {
+        let hull = to_bools("##..#.#####");
+        assert_eq!(parse_hull(&draw_hull(&hull), 4), [hull.clone(), vec![true; 4]].concat());
+    }
 }
@@ -134,130 +134,178 @@ fn parse(line: &str) -> Vec<Word> {
         .expect("Please type a number!")).collect::<Vec<Word>>();
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(PartialEq, Debug, Copy, Clone)]
 struct Packet {
     x: Word,
     y: Word
 }
 
-fn run_network(line: &str) -> Word {
-    let mut queues: HashMap<Word, Vec<Packet>> = HashMap::new();
-    let mut machines: Vec<Machine> = (0..50).map(|i| {
-        let mut machine = Machine::new(parse(line));
-        let output = run(&mut machine, &mut vec![i]);
-        // Push output to queues
-        for j in (0..output.len()).step_by(3) {
-            queues.entry(output[j])
-                .or_insert_with(|| Vec::new())
-                .push(Packet {x: output[j + 1], y: output[j + 2]});
-        }
-        machine
-    }).collect();
+const NAT: Word = 255;
 
-    loop {
-        for (i, mut machine) in machines.iter_mut().enumerate() {
-            // Assemble input
-            let queue = queues.remove(&(i as Word));
-            let mut input = if let Some(queue) = queue {
-                queue.iter().flat_map(|packet| vec![packet.x, packet.y]).collect()
-            } else {
-                vec![-1]
-            };
+// What the NAT wants the network to do next.
+#[derive(PartialEq, Debug, Copy, Clone)]
+enum Nat {
+    Continue,
+    Send(Word, Packet),
+    Stop(Word)
+}
 
-            // Run machine
-            let output = run(&mut machine, &mut input);
-            
-            if output.len() > 0 {
-                println!("Running {}", i);
-                println!("  Input: {:?}", input);
-                println!("  Output: {:?}", output);
-            }
+trait NatPolicy {
+    // Called with each packet sent to address 255
+    fn receive(&mut self, packet: Packet) -> Nat;
+    // Called whenever a round passes with no packets sent or waiting
+    fn idle(&mut self) -> Nat;
+}
+
+// Stops with the Y of the first packet sent to the NAT.
+struct FirstPacket;
+
+impl NatPolicy for FirstPacket {
+    fn receive(&mut self, packet: Packet) -> Nat {
+        return Nat::Stop(packet.y);
+    }
 
-            // Push output to queues
-            for j in (0..output.len()).step_by(3) {
-                queues.entry(output[j])
-                    .or_insert_with(|| Vec::new())
-                    .push(Packet {x: output[j + 1], y: output[j + 2]});
+    fn idle(&mut self) -> Nat {
+        return Nat::Continue;
+    }
+}
+
+// Resends the last packet it received to address 0 when the network goes idle, stopping
+// when it sends the same Y twice in a row.
+struct IdleResend {
+    last_received: Option<Packet>,
+    last_sent: Option<Packet>
+}
+
+impl IdleResend {
+    fn new() -> IdleResend {
+        return IdleResend { last_received: None, last_sent: None };
+    }
+}
+
+impl NatPolicy for IdleResend {
+    fn receive(&mut self, packet: Packet) -> Nat {
+        self.last_received = Some(packet);
+        return Nat::Continue;
+    }
+
+    fn idle(&mut self) -> Nat {
+        // Nothing has reached the NAT yet, so there's nothing to wake the network with
+        let packet = match self.last_received {
+            Some(packet) => packet,
+            None => return Nat::Continue
+        };
+        if let Some(last_sent) = self.last_sent {
+            if packet.y == last_sent.y {
+                return Nat::Stop(packet.y);
             }
         }
-        // Check if packet to 255, if so, return Y value
-        if let Some(queue) = queues.get(&255) {
-            return queue[0].y;
-        }
+        self.last_sent = Some(packet);
+        return Nat::Send(0, packet);
+    }
+}
+
+trait PacketObserver {
+    // Called with every packet sent, including those to and from the NAT
+    fn observe(&mut self, round: usize, from: Word, to: Word, packet: Packet);
+}
+
+impl PacketObserver for Vec<(usize, Word, Word, Packet)> {
+    fn observe(&mut self, round: usize, from: Word, to: Word, packet: Packet) {
+        self.push((round, from, to, packet));
     }
 }
 
-fn run_network_with_nat(line: &str) -> Word {
-    let mut last_nat_packet: Option<Packet> = None;
+// Writes one line per packet, timestamped with the scheduler round it was sent in.
+struct PacketCapture<W: Write> {
+    out: W
+}
 
+impl<W: Write> PacketObserver for PacketCapture<W> {
+    fn observe(&mut self, round: usize, from: Word, to: Word, packet: Packet) {
+        writeln!(self.out, "{}\t{}\t{}\t{}\t{}", round, from, to, packet.x, packet.y).expect("Failed to write capture");
+    }
+}
+
+// A machine on the network, with the words of any packet it has only partly sent so far.
+struct Nic {
+    machine: Machine,
+    pending: Vec<Word>,
+    // Consecutive polls that got -1 and produced no output
+    empty_polls: usize
+}
+
+impl Nic {
+    fn is_idle(&self) -> bool {
+        return self.pending.is_empty() && self.empty_polls >= 2;
+    }
+}
+
+// Buffers a NIC's output, sending each packet once all three of its words have arrived.
+fn send_all(pending: &mut Vec<Word>, output: &[Word], from: Word, round: usize, queues: &mut HashMap<Word, Vec<Packet>>, observer: &mut dyn PacketObserver) {
+    pending.extend_from_slice(output);
+    while pending.len() >= 3 {
+        let words: Vec<Word> = pending.drain(..3).collect();
+        let packet = Packet {x: words[1], y: words[2]};
+        observer.observe(round, from, words[0], packet);
+        queues.entry(words[0]).or_default().push(packet);
+    }
+}
+
+// The network is idle once nothing is queued and every NIC has polled empty twice in a row,
+// so a machine still working after a single -1 doesn't count.
+fn run_network(line: &str, nat: &mut dyn NatPolicy, observer: &mut dyn PacketObserver) -> Word {
     let mut queues: HashMap<Word, Vec<Packet>> = HashMap::new();
-    let mut machines: Vec<Machine> = (0..50).map(|i| {
-        let mut machine = Machine::new(parse(line));
-        let output = run(&mut machine, &mut vec![i]);
-        // Push output to queues
-        for j in (0..output.len()).step_by(3) {
-            queues.entry(output[j])
-                .or_insert_with(|| Vec::new())
-                .push(Packet {x: output[j + 1], y: output[j + 2]});
-        }
-        machine
+    let mut nics: Vec<Nic> = (0..50).map(|i| {
+        let mut nic = Nic { machine: Machine::new(parse(line)), pending: vec![], empty_polls: 0 };
+        let output = run(&mut nic.machine, &mut vec![i]);
+        send_all(&mut nic.pending, &output, i, 0, &mut queues, observer);
+        nic
     }).collect();
 
-    let mut last_sent_packet: Option<Packet> = None;
-
-    loop {
-        let mut all_empty = true;
-        for (i, mut machine) in machines.iter_mut().enumerate() {
+    for round in 1.. {
+        for (i, nic) in nics.iter_mut().enumerate() {
             // Assemble input
             let queue = queues.remove(&(i as Word));
+            let received = queue.is_some();
             let mut input = if let Some(queue) = queue {
-                all_empty = false;
                 queue.iter().flat_map(|packet| vec![packet.x, packet.y]).collect()
             } else {
                 vec![-1]
             };
 
             // Run machine
-            let output = run(&mut machine, &mut input);
-            
-            if output.len() > 0 {
-                //println!("Running {}", i);
-                //println!("  Input: {:?}", input);
-                //println!("  Output: {:?}", output);
-            }
-
-            // Push output to queues
-            for j in (0..output.len()).step_by(3) {
-                all_empty = false;
-                queues.entry(output[j])
-                    .or_insert_with(|| Vec::new())
-                    .push(Packet {x: output[j + 1], y: output[j + 2]});
+            let output = run(&mut nic.machine, &mut input);
+            if received || !output.is_empty() {
+                nic.empty_polls = 0;
+            } else {
+                nic.empty_polls += 1;
             }
+            send_all(&mut nic.pending, &output, i as Word, round, &mut queues, observer);
         }
-        if let Some(queue) = queues.get_mut(&255) {
-            if queue.len() > 0 {
-                last_nat_packet = queue.pop();
-                queue.clear();
+        let idle = (0..nics.len()).all(|i| !queues.contains_key(&(i as Word))) && nics.iter().all(Nic::is_idle);
+
+        // The NAT sees every packet sent to it, and anything it sends wakes the network up
+        let mut woken = false;
+        for packet in queues.remove(&NAT).unwrap_or_default() {
+            match nat.receive(packet) {
+                Nat::Continue => {},
+                Nat::Send(to, packet) => {
+                    send_all(&mut vec![], &[to, packet.x, packet.y], NAT, round, &mut queues, observer);
+                    woken = true;
+                },
+                Nat::Stop(y) => return y
             }
         }
-        // Send nat packet to 0 when all_empty
-        if all_empty {
-            if let Some(packet) = last_nat_packet {
-                println!("!!! Sending NAT packet {:?}", packet);
-                println!("  NAT queue: {:?}", queues.get(&255));
-                if let Some(last_sent_packet) = last_sent_packet {
-                    println!("  Last packet {:?}", last_sent_packet);
-                    if packet.y == last_sent_packet.y {
-                        return packet.y;
-                    }
-                }
-                queues.entry(0).or_insert(vec![]).push(packet);
-                last_sent_packet = Some(packet);
-            } else {
-                panic!("all_empty but no nat packet");
+        if idle && !woken {
+            match nat.idle() {
+                Nat::Continue => {},
+                Nat::Send(to, packet) => send_all(&mut vec![], &[to, packet.x, packet.y], NAT, round, &mut queues, observer),
+                Nat::Stop(y) => return y
             }
         }
     }
+    unreachable!();
 }
 
 fn main() {
@@ -266,9 +314,10 @@ fn main() {
     for line in reader.lines() {
         let line = line.expect("Failed to read");
 
-        //println!("{}", run_network(&line));
-        
-        println!("{}", run_network_with_nat(&line));
+        println!("{}", run_network(&line, &mut FirstPacket, &mut vec![]));
+
+        let mut capture = PacketCapture { out: File::create("network.cap").expect("Failed to create capture") };
+        println!("{}", run_network(&line, &mut IdleResend::new(), &mut capture));
     }
 }
 
@@ -340,4 +389,104 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    #[test]
+    fn test_first_packet() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let mut packets = vec![];
+        assert_eq!(run_network(&line, &mut FirstPacket, &mut packets), 20665);
+        let (_, _, to, packet) = packets.last().unwrap();
+        assert_eq!((*to, packet.y), (NAT, 20665));
+    }
+
+    #[test]
+    fn test_idle_resend() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let mut capture = PacketCapture { out: vec![] };
+        assert_eq!(run_network(&line, &mut IdleResend::new(), &mut capture), 13358);
+        let capture = String::from_utf8(capture.out).unwrap();
+        let resent: Vec<&str> = capture.lines().filter(|l| l.split('\t').nth(1) == Some("255")).collect();
+        assert!(resent.iter().all(|l| l.split('\t').nth(2) == Some("0")));
+        assert!(resent.last().unwrap().ends_with("\t13358"));
+    }
+
+    // Sends whatever it receives straight back to address 0, giving up after a few goes.
+    struct Echo(usize);
+
+    impl NatPolicy for Echo {
+        fn receive(&mut self, packet: Packet) -> Nat {
+            self.0 -= 1;
+            return if self.0 == 0 {Nat::Stop(packet.x)} else {Nat::Send(0, packet)};
+        }
+
+        fn idle(&mut self) -> Nat {
+            return Nat::Continue;
+        }
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let mut packets = vec![];
+        run_network(&line, &mut Echo(3), &mut packets);
+        assert_eq!(packets.iter().filter(|(_, from, to, _)| *from == NAT && *to == 0).count(), 2);
+        assert!(packets.windows(2).all(|w| w[0].0 <= w[1].0));
+    }
+
+    // Machine 0 sends (255, 7, 42) one word at a time, polling between each; the rest just poll.
+    const SPLIT_SENDER: &str = "3,100,1005,100,15,104,255,3,101,104,7,3,101,104,42,3,101,1105,1,15";
+
+    #[test]
+    fn test_partial_packets() {
+        let mut packets = vec![];
+        assert_eq!(run_network(SPLIT_SENDER, &mut FirstPacket, &mut packets), 42);
+        assert_eq!(packets, vec![(2, 0, NAT, Packet {x: 7, y: 42})]);
+    }
+
+    // Machine 0 polls twice before sending (255, 3, 9), then polls forever; the rest just poll.
+    const SLOW_SENDER: &str = "3,100,1005,100,15,3,101,3,101,104,255,104,3,104,9,3,101,1105,1,15";
+
+    #[test]
+    fn test_idle_needs_two_empty_polls() {
+        let mut packets = vec![];
+        assert_eq!(run_network(SLOW_SENDER, &mut IdleResend::new(), &mut packets), 9);
+        let packet = Packet {x: 3, y: 9};
+        assert_eq!(packets, vec![(2, 0, NAT, packet), (4, NAT, 0, packet)]);
+    }
+
+    // Machine 0 polls three times, so the network goes idle before it sends (255, 5, 8)
+    const LATE_SENDER: &str = "3,100,1005,100,17,3,101,3,101,3,101,104,255,104,5,104,8,3,101,1105,1,17";
+
+    #[test]
+    fn test_idle_before_any_packet() {
+        assert_eq!(IdleResend::new().idle(), Nat::Continue);
+        let mut packets = vec![];
+        assert_eq!(run_network(LATE_SENDER, &mut IdleResend::new(), &mut packets), 8);
+        let packet = Packet {x: 5, y: 8};
+        assert_eq!(packets, vec![(3, 0, NAT, packet), (5, NAT, 0, packet)]);
+    }
+
+    // Sends each packet it receives back to address 0, stopping on the one with Y 3.
+    struct EchoAll;
+
+    impl NatPolicy for EchoAll {
+        fn receive(&mut self, packet: Packet) -> Nat {
+            return if packet.y == 3 {Nat::Stop(packet.x)} else {Nat::Send(0, packet)};
+        }
+
+        fn idle(&mut self) -> Nat {
+            return Nat::Continue;
+        }
+    }
+
+    // Machine 0 sends (255, 1, 1), (255, 2, 2) and (255, 3, 3) in one go; the rest just poll.
+    const BURST_SENDER: &str = "3,100,1005,100,23,104,255,104,1,104,1,104,255,104,2,104,2,104,255,104,3,104,3,3,101,1105,1,23";
+
+    #[test]
+    fn test_nat_sees_every_packet() {
+        let mut packets = vec![];
+        assert_eq!(run_network(BURST_SENDER, &mut EchoAll, &mut packets), 3);
+        let echoed: Vec<Word> = packets.iter().filter(|(_, from, _, _)| *from == NAT).map(|(_, _, _, packet)| packet.y).collect();
+        assert_eq!(echoed, vec![1, 2]);
+    }
 }
@@ -2,12 +2,21 @@ use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
 use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::fmt;
 
-#[macro_use] extern crate text_io;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
 
 type Word = i64;
 
+#[derive(Clone)]
 struct Machine {
     mem: Vec<Word>,
     ip: usize,
@@ -144,24 +153,265 @@ fn from_ascii(input: &str) -> Vec<Word> {
     input.as_bytes().iter().map(|c| *c as u8 as Word).collect()
 }
 
+const DIRECTIONS: [&str; 4] = ["north", "south", "east", "west"];
+
+const HELP: &str = "Game commands: north, south, east, west, take <item>, drop <item>, inv
+:map            show the rooms visited so far
+:undo           take back the last game command
+:save [name]    remember the current state (default name \"quick\")
+:load [name]    go back to a remembered state
+:help           show this help
+:quit           leave";
+
+#[derive(Clone, PartialEq, Debug)]
+struct Room {
+    name: String,
+    doors: Vec<String>,
+    items: Vec<String>
+}
+
+// Every room described in some output, in order. There is more than one when the droid is moved
+// on without being asked, e.g. ejected from the security checkpoint.
+fn parse_rooms(text: &str) -> Vec<Room> {
+    let mut rooms: Vec<Room> = vec![];
+    let mut list = None;
+    for line in text.lines() {
+        if line.starts_with("== ") && line.ends_with(" ==") {
+            rooms.push(Room { name: line[3..line.len() - 3].to_string(), doors: vec![], items: vec![] });
+            list = None;
+        } else if line == "Doors here lead:" || line == "Items here:" {
+            list = Some(line);
+        } else if let Some(entry) = line.strip_prefix("- ") {
+            if let Some(room) = rooms.last_mut() {
+                match list {
+                    Some("Doors here lead:") => room.doors.push(entry.to_string()),
+                    Some("Items here:") => room.items.push(entry.to_string()),
+                    _ => {}
+                }
+            }
+        } else if !line.is_empty() {
+            list = None;
+        }
+    }
+    return rooms;
+}
+
+// Items mentioned anywhere in some output, whether lying in a room or in the inventory.
+fn parse_items(text: &str) -> Vec<String> {
+    return text.lines()
+        .filter_map(|line| line.strip_prefix("- "))
+        .filter(|entry| !DIRECTIONS.contains(entry))
+        .map(|entry| entry.to_string())
+        .collect();
+}
+
+// The rooms seen so far and where each door was found to lead.
+#[derive(Default)]
+struct Map {
+    rooms: BTreeMap<String, Room>,
+    exits: BTreeMap<(String, String), String>
+}
+
+impl Map {
+    fn visit(&mut self, from: Option<&str>, direction: Option<&str>, rooms: &[Room]) {
+        if let (Some(from), Some(direction), Some(to)) = (from, direction, rooms.first()) {
+            self.exits.insert((from.to_string(), direction.to_string()), to.name.clone());
+        }
+        for room in rooms {
+            self.rooms.insert(room.name.clone(), room.clone());
+        }
+    }
+}
+
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for room in self.rooms.values() {
+            let doors: Vec<String> = room.doors.iter().map(|door| {
+                let to = self.exits.get(&(room.name.clone(), door.clone())).map_or("?", |to| to.as_str());
+                format!("{} -> {}", door, to)
+            }).collect();
+            write!(f, "{}: {}", room.name, doors.join(", "))?;
+            if !room.items.is_empty() {
+                write!(f, " [{}]", room.items.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        return Ok(());
+    }
+}
+
+#[derive(Clone)]
+struct Snapshot {
+    machine: Machine,
+    room: Option<String>
+}
+
+struct Session {
+    machine: Machine,
+    room: Option<String>,
+    map: Map,
+    items: BTreeSet<String>,
+    undo: Vec<Snapshot>,
+    saves: HashMap<String, Snapshot>
+}
+
+fn halted(machine: &Machine) -> bool {
+    return machine.mem[machine.ip] % 100 == 99;
+}
+
+impl Session {
+    // Boots the droid, returning the session and its opening text
+    fn new(program: Vec<Word>) -> (Session, String) {
+        let mut session = Session {
+            machine: Machine::new(program), room: None, map: Map::default(),
+            items: BTreeSet::new(), undo: vec![], saves: HashMap::new()
+        };
+        let text = session.run(None, vec![]);
+        return (session, text);
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        return Snapshot { machine: self.machine.clone(), room: self.room.clone() };
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.machine = snapshot.machine;
+        self.room = snapshot.room;
+    }
+
+    fn run(&mut self, direction: Option<&str>, mut input: Vec<Word>) -> String {
+        let text = to_ascii(&run(&mut self.machine, &mut input));
+        let rooms = parse_rooms(&text);
+        self.map.visit(self.room.as_deref(), direction, &rooms);
+        if let Some(room) = rooms.last() {
+            self.room = Some(room.name.clone());
+        }
+        self.items.extend(parse_items(&text));
+        return text;
+    }
+
+    // Sends a command to the droid, keeping a snapshot so it can be undone
+    fn send(&mut self, command: &str) -> String {
+        if halted(&self.machine) {
+            return "The droid has stopped; :undo or :load to carry on.".to_string();
+        }
+        self.undo.push(self.snapshot());
+        let direction = DIRECTIONS.iter().find(|&&d| d == command).cloned();
+        let mut input = from_ascii(command);
+        input.push(10);
+        let mut text = self.run(direction, input);
+        if halted(&self.machine) {
+            text.push_str("\nThe droid has stopped; :undo or :load to carry on.");
+        }
+        return text;
+    }
+
+    fn execute(&mut self, line: &str) -> String {
+        let line = line.trim();
+        let (command, argument) = match line.find(' ') {
+            Some(space) => (&line[..space], line[space + 1..].trim()),
+            None => (line, "")
+        };
+        let name = if argument.is_empty() {"quick"} else {argument};
+        return match command {
+            ":help" => HELP.to_string(),
+            ":map" => self.map.to_string(),
+            ":undo" => match self.undo.pop() {
+                Some(snapshot) => {
+                    self.restore(snapshot);
+                    format!("Undone, back in {}", self.room.as_deref().unwrap_or("?"))
+                },
+                None => "Nothing to undo".to_string()
+            },
+            ":save" => {
+                self.saves.insert(name.to_string(), self.snapshot());
+                format!("Saved {}", name)
+            },
+            ":load" => match self.saves.get(name).cloned() {
+                Some(snapshot) => {
+                    self.undo.push(self.snapshot());
+                    self.restore(snapshot);
+                    format!("Loaded {}, back in {}", name, self.room.as_deref().unwrap_or("?"))
+                },
+                None => format!("No save called {}", name)
+            },
+            _ if command.starts_with(':') => format!("Unknown command {}, try :help", command),
+            _ => self.send(line)
+        };
+    }
+
+    // Every whole command worth offering for completion
+    fn commands(&self) -> Vec<String> {
+        let mut commands: Vec<String> = DIRECTIONS.iter().map(|d| d.to_string()).collect();
+        commands.push("inv".to_string());
+        for item in &self.items {
+            commands.push(format!("take {}", item));
+            commands.push(format!("drop {}", item));
+        }
+        for meta in &[":help", ":map", ":undo", ":save", ":load", ":quit"] {
+            commands.push(meta.to_string());
+        }
+        for name in self.saves.keys() {
+            commands.push(format!(":load {}", name));
+        }
+        return commands;
+    }
+}
+
+// Completes the whole line, as item names contain spaces.
+fn complete(commands: &[String], prefix: &str) -> Vec<String> {
+    return commands.iter().filter(|command| command.starts_with(prefix)).cloned().collect();
+}
+
+struct CommandCompleter {
+    commands: Vec<String>
+}
+
+impl Completer for CommandCompleter {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        return Ok((0, complete(&self.commands, &line[..pos])));
+    }
+}
+
+impl Hinter for CommandCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for CommandCompleter {}
+
+impl Validator for CommandCompleter {}
+
+impl Helper for CommandCompleter {}
+
 fn main() {
     let file = File::open("input").expect("Failed to open input");
     let reader = BufReader::new(file);
     for line in reader.lines() {
         let line = line.expect("Failed to read");
-        
-        let mut machine = Machine::new(parse(&line));
 
-        let mut input = vec![];
+        let (mut session, text) = Session::new(parse(&line));
+        println!("{}", text);
+        println!("Type :help for REPL commands");
 
+        let mut editor: Editor<CommandCompleter, DefaultHistory> = Editor::new().expect("Failed to start line editor");
+        editor.set_helper(Some(CommandCompleter { commands: session.commands() }));
         loop {
-            let output = run(&mut machine, &mut input);
-
-            println!("{}", to_ascii(&output));
-
-            let line: String = read!("{}\n");
-            input = from_ascii(&line);
-            input.push(10);
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+                Err(error) => panic!("Failed to read: {}", error)
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line.as_str()).expect("Failed to record history");
+            if line.trim() == ":quit" {
+                break;
+            }
+            println!("{}", session.execute(&line));
+            editor.helper_mut().expect("Helper was set").commands = session.commands();
         }
     }
 }
@@ -234,4 +484,41 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    #[test]
+    fn test_parse_rooms() {
+        let text = "== Security Checkpoint ==\nIn the next room, a pressure-sensitive floor will verify your identity.\n\nDoors here lead:\n- north\n- south\n\nA loud, robotic voice says \"Alert!\" and you are ejected back to the checkpoint.\n\n== Hull Breach ==\nYou got in through a hole in the floor here.\n\nDoors here lead:\n- east\n\nItems here:\n- mug\n\nCommand?\n";
+        let rooms = parse_rooms(text);
+        assert_eq!(rooms.iter().map(|room| room.name.as_str()).collect::<Vec<_>>(), vec!["Security Checkpoint", "Hull Breach"]);
+        assert_eq!(rooms[0].doors, vec!["north", "south"]);
+        assert!(rooms[0].items.is_empty());
+        assert_eq!(rooms[1].items, vec!["mug"]);
+        assert_eq!(parse_items(text), vec!["mug"]);
+    }
+
+    #[test]
+    fn test_session() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let (mut session, text) = Session::new(parse(line.trim()));
+        assert!(text.contains("== Hull Breach =="));
+        assert_eq!(session.room.as_deref(), Some("Hull Breach"));
+
+        session.execute("south");
+        assert_eq!(session.room.as_deref(), Some("Science Lab"));
+        assert!(session.map.to_string().contains("Hull Breach: east -> ?, south -> Science Lab, west -> ?"));
+        assert!(session.commands().contains(&"take weather machine".to_string()));
+        assert_eq!(complete(&session.commands(), "take w"), vec!["take weather machine"]);
+
+        session.execute(":save lab");
+        session.execute("north");
+        assert_eq!(session.room.as_deref(), Some("Hull Breach"));
+        assert_eq!(session.execute(":undo"), "Undone, back in Science Lab");
+        session.execute(":undo");
+        assert_eq!(session.room.as_deref(), Some("Hull Breach"));
+        assert_eq!(session.execute(":load lab"), "Loaded lab, back in Science Lab");
+        assert_eq!(session.execute(":load nowhere"), "No save called nowhere");
+
+        // The map survives going back in time
+        assert!(session.map.rooms.contains_key("Science Lab"));
+    }
 }
@@ -2,6 +2,9 @@ use std::fs::File;
 use std::io::{self, prelude::*, BufReader};
 use std::cmp;
 
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 struct Point {
     x: i32,
@@ -16,84 +19,174 @@ impl Point
     }
 }
 
-fn parse_wire(line: &str) -> Vec<Point> {
+fn parse_wire(line: &str) -> Result<Vec<Point>, String> {
     let mut current = Point {x: 0, y: 0, distance: 0};
 
     return line.split(",").map(|item| {
         let item = item.trim();
         let direction = item.chars().nth(0);
-        let distance: i32 = item[1..].parse().expect("Not a number");
+        let distance: i32 = item.get(1..).and_then(|d| d.parse().ok()).ok_or(format!("{} has no distance", item))?;
         current = match direction {
             Some('R') => Point { x: current.x + distance, distance: current.distance + distance, ..current},
             Some('L') => Point { x: current.x - distance, distance: current.distance + distance, ..current},
             Some('D') => Point { y: current.y + distance, distance: current.distance + distance, ..current},
             Some('U') => Point { y: current.y - distance, distance: current.distance + distance, ..current},
-            _ => panic!("Unknown direction {:?}", direction)
+            _ => return Err(format!("Unknown direction in {}", item))
         };
-        return current.clone();
-    }).collect::<Vec<Point>>();
+        return Ok(current.clone());
+    }).collect::<Result<Vec<Point>, String>>();
 }
 
-
+// A straight run of one wire. `f.distance` is the steps the wire has taken when it gets to `f`.
 #[derive(PartialEq, Debug, Clone)]
-struct Line {
+struct Segment {
+    wire: usize,
     f: Point,
-    t: Point,
+    t: Point
 }
 
-impl Line
-{
-    pub fn new(f: Point, t: Point) -> Line {
-        Line { f: f, t: t }
-    }
-}
-
-fn intersection(la: &Line, lb: &Line) -> Option<Point> {
-    if la.f.x == la.t.x {
-        let lax = la.f.x;
-        let laymin = cmp::min(la.f.y, la.t.y);
-        let laymax = cmp::max(la.f.y, la.t.y);
-        if lb.f.y == lb.t.y {
-            let lby = lb.f.y;
-            let lbxmin = cmp::min(lb.f.x, lb.t.x);
-            let lbxmax = cmp::max(lb.f.x, lb.t.x);
-            if lbxmin <= lax && lax <= lbxmax && laymin <= lby && lby <= laymax {
-                // Intersection at lax, lby
-                let ladistance = (la.f.y - lby).abs();
-                let lbdistance = (lb.f.x - lax).abs();
-                return Some(Point {x: lax, y: lby, distance: la.f.distance + ladistance + lb.f.distance + lbdistance});
+impl Segment {
+    // Single points count as horizontal
+    fn horizontal(&self) -> bool {
+        return self.f.y == self.t.y;
+    }
+
+    fn x_range(&self) -> (i32, i32) {
+        return (cmp::min(self.f.x, self.t.x), cmp::max(self.f.x, self.t.x));
+    }
+
+    fn y_range(&self) -> (i32, i32) {
+        return (cmp::min(self.f.y, self.t.y), cmp::max(self.f.y, self.t.y));
+    }
+
+    fn steps_to(&self, x: i32, y: i32) -> i32 {
+        return self.f.distance + (x - self.f.x).abs() + (y - self.f.y).abs();
+    }
+}
+
+fn segments(wires: &[Vec<Point>]) -> Vec<Segment> {
+    return wires.iter().enumerate().flat_map(|(wire, points)| {
+        let mut last = Point::new(0, 0, 0);
+        points.iter().map(move |point| {
+            let segment = Segment { wire, f: last, t: *point };
+            last = *point;
+            segment
+        })
+    }).collect();
+}
+
+// A point more than one wire goes through, other than the origin
+#[derive(PartialEq, Debug, Clone)]
+struct Crossing {
+    x: i32,
+    y: i32,
+    wires: Vec<usize>,
+    // Manhattan distance from the origin
+    distance: i32,
+    // The steps each wire takes to first get here, added up
+    steps: i32
+}
+
+// For each point, the fewest steps each wire going through it takes to get there
+type Hits = HashMap<(i32, i32), BTreeMap<usize, i32>>;
+
+fn record(hits: &mut Hits, x: i32, y: i32, a: &Segment, b: &Segment) {
+    if a.wire == b.wire || (x, y) == (0, 0) {
+        return;
+    }
+    let wires = hits.entry((x, y)).or_default();
+    for segment in &[a, b] {
+        let steps = segment.steps_to(x, y);
+        let best = wires.entry(segment.wire).or_insert(steps);
+        *best = cmp::min(*best, steps);
+    }
+}
+
+// Segments on the same line, sorted by where they start along it, overlap wherever one starts
+// before an earlier one ends. Every point in the overlap is a crossing.
+fn collinear_overlaps(hits: &mut Hits, line: &[&Segment], horizontal: bool) {
+    let range = |s: &Segment| if horizontal {s.x_range()} else {s.y_range()};
+    let mut active: Vec<&Segment> = vec![];
+    for &segment in line {
+        let (start, end) = range(segment);
+        active.retain(|other| range(other).1 >= start);
+        for other in &active {
+            for along in start..=cmp::min(end, range(other).1) {
+                let (x, y) = if horizontal {(along, segment.f.y)} else {(segment.f.x, along)};
+                record(hits, x, y, other, segment);
             }
         }
-        return None;
-    } else {
-        if lb.f.x == lb.t.x {
-            return intersection(lb, la);
-        } else {
-            return None;
-        }
+        active.push(segment);
     }
 }
 
-fn find_intersections(a_list: &Vec<Point>, b_list: &Vec<Point>) -> Vec<Point> {
-    let mut output = Vec::new();
-
-    let mut last_a = Point { x: 0, y: 0, distance: 0 };
+// Sweeps left to right keeping the horizontal segments under the sweep line by height, so each
+// vertical segment only meets the horizontals it spans. Collinear runs are found separately by
+// sorting each line's segments.
+fn find_crossings(wires: &[Vec<Point>]) -> Vec<Crossing> {
+    let segments = segments(wires);
+    let mut hits = Hits::new();
 
-    for a in a_list {
-        let mut last_b = Point { x: 0, y: 0, distance: 0 };
-        for b in b_list {
-            let x = intersection(&Line::new(last_a, a.clone()), &Line::new(last_b, b.clone()));
-            if let Some(x) = x {
-                if x != (Point { x: 0, y: 0, distance: 0 }) {
-                    output.push(x);
+    // Events at the same x: horizontals start, then verticals are checked, then horizontals end
+    let mut events: Vec<(i32, u8, usize)> = vec![];
+    for (i, segment) in segments.iter().enumerate() {
+        let (min_x, max_x) = segment.x_range();
+        if segment.horizontal() {
+            events.push((min_x, 0, i));
+            events.push((max_x, 2, i));
+        } else {
+            events.push((min_x, 1, i));
+        }
+    }
+    events.sort();
+    let mut active: BTreeMap<i32, Vec<usize>> = BTreeMap::new();
+    for (x, kind, i) in events {
+        let segment = &segments[i];
+        match kind {
+            0 => active.entry(segment.f.y).or_default().push(i),
+            1 => {
+                let (min_y, max_y) = segment.y_range();
+                for (&y, horizontals) in active.range(min_y..=max_y) {
+                    for &h in horizontals {
+                        record(&mut hits, x, y, &segments[h], segment);
+                    }
+                }
+            },
+            _ => {
+                let horizontals = active.get_mut(&segment.f.y).unwrap();
+                horizontals.retain(|&h| h != i);
+                if horizontals.is_empty() {
+                    active.remove(&segment.f.y);
                 }
             }
-            last_b = b.clone();
         }
-        last_a = a.clone();
     }
 
-    return output;
+    let mut lines: BTreeMap<(bool, i32), Vec<&Segment>> = BTreeMap::new();
+    for segment in &segments {
+        let key = if segment.horizontal() {(true, segment.f.y)} else {(false, segment.f.x)};
+        lines.entry(key).or_default().push(segment);
+    }
+    for ((horizontal, _), mut line) in lines {
+        line.sort_by_key(|s| if horizontal {s.x_range().0} else {s.y_range().0});
+        collinear_overlaps(&mut hits, &line, horizontal);
+    }
+
+    let mut crossings: Vec<Crossing> = hits.into_iter().map(|((x, y), wires)| Crossing {
+        x, y,
+        wires: wires.keys().cloned().collect(),
+        distance: x.abs() + y.abs(),
+        steps: wires.values().sum()
+    }).collect();
+    crossings.sort_by_key(|c| (c.steps, c.distance, c.x, c.y));
+    return crossings;
+}
+
+// Crossings between two wires, with `distance` as the steps to get there
+fn find_intersections(a_list: &Vec<Point>, b_list: &Vec<Point>) -> Vec<Point> {
+    return find_crossings(&[a_list.clone(), b_list.clone()]).iter()
+        .map(|c| Point::new(c.x, c.y, c.steps))
+        .collect();
 }
 
 fn nearest_point(p_list: Vec<Point>) -> Option<Point> {
@@ -137,13 +230,20 @@ fn closest_intersection(a_list: &Vec<Point>, b_list: &Vec<Point>) -> Option<Poin
 fn main() {
     let file = File::open("input").expect("Failed to open input");
     let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-    let a_list = parse_wire(&(lines.next().expect("Failed to read").expect("WAT")));
-    let b_list = parse_wire(&(lines.next().expect("Failed to read").expect("WAT")));
+    let wires: Vec<Vec<Point>> = reader.lines().enumerate().map(|(i, line)| {
+        let line = line.expect("Failed to read");
+        return parse_wire(&line).unwrap_or_else(|error| panic!("Wire {}: {}", i + 1, error));
+    }).collect();
 
-    let p = nearest_intersection(&a_list, &b_list).expect("No intersections");
+    let crossings = find_crossings(&wires);
+    for c in &crossings {
+        println!("({}, {}) wires {:?} distance {} steps {}", c.x, c.y, c.wires, c.distance, c.steps);
+    }
 
-    let q = closest_intersection(&a_list, &b_list).expect("No intersections");
+    // The puzzle's answers are for the first two wires
+    let p = nearest_intersection(&wires[0], &wires[1]).expect("No intersections");
+
+    let q = closest_intersection(&wires[0], &wires[1]).expect("No intersections");
 
     println!("Nearest: {:?} Closest: {:?}", p, q);
 }
@@ -162,7 +262,7 @@ mod tests {
 
     #[test]
     fn test_parse_wire() {
-        let points = parse_wire("R8,U5,L5,D3");
+        let points = parse_wire("R8,U5,L5,D3").unwrap();
         assert_eq!(points[0], Point::new(8, 0, 8));
         assert_eq!(points[1], Point::new(8, -5, 13));
         assert_eq!(points[2], Point::new(3, -5, 18));
@@ -171,41 +271,93 @@ mod tests {
 
     #[test]
     fn test_intersection() {
-        assert_eq!(intersection(
-            &Line::new(
-                Point::new(0, 0, 5),
-                Point::new(0, 10, 15)
-            ),
-            &Line::new(
-                Point::new(15, 3, 80),
-                Point::new(-4, 3, 99)
-            )),
-            Some(Point::new(0,3, 5 + 3 + 80 + 15))
-        );
+        let mut hits = Hits::new();
+        record(&mut hits, 0, 3,
+            &Segment { wire: 0, f: Point::new(0, 0, 5), t: Point::new(0, 10, 15) },
+            &Segment { wire: 1, f: Point::new(15, 3, 80), t: Point::new(-4, 3, 99) });
+        assert_eq!(hits[&(0, 3)].values().sum::<i32>(), 5 + 3 + 80 + 15);
     }
 
     #[test]
     fn test_find_intersections() {
-        let x_list = find_intersections(&parse_wire("R8,U5,L5,D3"), &parse_wire("U7,R6,D4,L4"));
+        let x_list = find_intersections(&parse_wire("R8,U5,L5,D3").unwrap(), &parse_wire("U7,R6,D4,L4").unwrap());
         assert_eq!(x_list[0], Point::new(6, -5, 30));
         assert_eq!(x_list[1], Point::new(3, -3, 40));
     }
     
     #[test]
     fn test_nearest_point() {
-        let points = parse_wire("R8,U5,L5,D3");
+        let points = parse_wire("R8,U5,L5,D3").unwrap();
         assert_eq!(nearest_point(points), Some(Point::new(3, -2, 21)));
     }
 
     #[test]
     fn test_nearest_intersection() {
-        let x = nearest_intersection(&parse_wire("R8,U5,L5,D3"), &parse_wire("U7,R6,D4,L4"));
+        let x = nearest_intersection(&parse_wire("R8,U5,L5,D3").unwrap(), &parse_wire("U7,R6,D4,L4").unwrap());
         assert_eq!(x, Some(Point::new(3, -3, 40)));
     }
     
     #[test]
     fn test_closest_intersection() {
-        let x = closest_intersection(&parse_wire("R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51"), &parse_wire("U98,R91,D20,R16,D67,R40,U7,R15,U6,R7")).unwrap();
+        let x = closest_intersection(&parse_wire("R98,U47,R26,D63,R33,U87,L62,D20,R33,U53,R51").unwrap(), &parse_wire("U98,R91,D20,R16,D67,R40,U7,R15,U6,R7").unwrap()).unwrap();
         assert_eq!(x.distance, 410);
     }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(parse_wire("R8,X5"), Err("Unknown direction in X5".to_string()));
+        assert_eq!(parse_wire("R8,U"), Err("U has no distance".to_string()));
+        assert_eq!(parse_wire("R8,Ufive"), Err("Ufive has no distance".to_string()));
+    }
+
+    #[test]
+    fn test_collinear_overlap() {
+        // Both wires run along y = -2 from x = 3 to 5
+        let a = parse_wire("U2,R6,U3").unwrap();
+        let b = parse_wire("R3,U2,R2,D1,L3").unwrap();
+        let crossings = find_crossings(&[a, b]);
+        let points: Vec<(i32, i32, i32)> = crossings.iter().map(|c| (c.x, c.y, c.steps)).collect();
+        assert_eq!(points, vec![(3, -2, 10), (4, -2, 12), (5, -2, 14)]);
+        assert!(crossings.iter().all(|c| c.wires == vec![0, 1]));
+    }
+
+    #[test]
+    fn test_many_wires() {
+        let wires: Vec<Vec<Point>> = ["R8,U5,L5,D3", "U7,R6,D4,L4", "U3,R10"].iter().map(|w| parse_wire(w).unwrap()).collect();
+        let crossings = find_crossings(&wires);
+        // (3, -3) is on all three wires, (8, -3) only on the first and third
+        let at = |x: i32, y: i32| crossings.iter().find(|c| (c.x, c.y) == (x, y)).unwrap();
+        assert_eq!(at(3, -3).wires, vec![0, 1, 2]);
+        assert_eq!(at(3, -3).steps, 20 + 20 + 6);
+        assert_eq!(at(3, -3).distance, 6);
+        assert_eq!(at(8, -3).wires, vec![0, 2]);
+        assert_eq!(at(8, -3).steps, 11 + 11);
+        assert_eq!(at(6, -5).wires, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_matches_pairwise() {
+        // Every crossing the sweep finds is on both wires, and every point on both is found
+        let a = parse_wire("R75,D30,R83,U83,L12,D49,R71,U7,L72").unwrap();
+        let b = parse_wire("U62,R66,U55,R34,D71,R55,D58,R83").unwrap();
+        let visited = |wire: &Vec<Point>| {
+            let mut seen = std::collections::HashSet::new();
+            let mut last = Point::new(0, 0, 0);
+            for p in wire {
+                let (dx, dy) = ((p.x - last.x).signum(), (p.y - last.y).signum());
+                while (last.x, last.y) != (p.x, p.y) {
+                    last = Point::new(last.x + dx, last.y + dy, 0);
+                    seen.insert((last.x, last.y));
+                }
+            }
+            seen
+        };
+        let mut expected: Vec<(i32, i32)> = visited(&a).intersection(&visited(&b)).cloned().collect();
+        expected.sort();
+        let crossings = find_crossings(&[a, b]);
+        let mut found: Vec<(i32, i32)> = crossings.iter().map(|c| (c.x, c.y)).collect();
+        found.sort();
+        assert_eq!(found, expected);
+        assert_eq!(crossings[0].steps, 610);
+    }
 }
@@ -1,90 +1,185 @@
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
+use std::fmt;
 
-fn addr(ip: usize, offset: i32) -> usize {
-    return (ip as i32 + offset) as usize;
+type Word = i64;
+
+struct Machine {
+    mem: Vec<Word>,
+    ip: usize,
+    relative_base: Word
+}
+
+
+impl Machine
+{
+    pub fn new(mem: Vec<Word>) -> Machine {
+        Machine { mem: mem, ip: 0, relative_base: 0 }
+    }
+}
+
+type AddrOrImm = Result<usize, Word>;
+
+fn get_at(machine: &Machine, addr: usize) -> Word {
+    if addr >= machine.mem.len() {
+        return 0;
+    }
+    return machine.mem[addr];
+}
+
+fn to_address(value: Word) -> usize {
+    if value < 0 {
+        panic!("Negative Intcode address {}", value);
+    }
+    return value as usize;
 }
 
-fn get(mem: &mut Vec<i32>, ip: usize, offset: i32, access: i32) -> i32 {
+fn addr(machine: &Machine, offset: Word, access: Word) -> AddrOrImm {
     let mut a = access;
     for _ in 1..offset {
         a /= 10;
     }
     a = a % 10;
-    let addr = addr(ip, offset);
-    println!("get a: {} offset:{} addr: {}", a, offset, addr);
+    let instr_value = get_at(machine, machine.ip + offset as usize);
+    //println!("instr_value: {}, offset: {}, access: {}", instr_value, offset, access);
     return match a {
-        0 => mem[mem[addr] as usize],
-        1 => mem[addr],
+        0 => Ok(to_address(instr_value)),
+        1 => Err(instr_value),
+        2 => Ok(to_address(machine.relative_base.checked_add(instr_value).expect("Intcode overflow in relative address"))),
         _ => panic!("Unknown access type {} (offset: {} access: {})", a, offset, access)
     };
 }
 
-fn run(mem: &mut Vec<i32>, input: &mut Vec<i32>) -> Vec<i32> {
-    let mut ip: usize = 0;
+fn get(machine: &Machine, offset: Word, access: Word) -> Word {
+    let a = addr(machine, offset, access);
+    return match a {
+        Ok(address) => get_at(machine, address),
+        Err(immediate) => immediate
+    };
+}
+
+fn set(machine: &mut Machine, offset: Word, to: Word) {
+    //println!("Set {} to {}", offset, to);
+    let offset_as_usize = offset as usize;
+    if machine.mem.len() <= offset_as_usize {
+        machine.mem.resize(offset_as_usize + 1, 0);
+    }
+    machine.mem[offset_as_usize] = to;
+}
+
+// Runs until the machine halts or needs input, pairing each output with the address of the
+// instruction that wrote it.
+fn run_traced(machine: &mut Machine, input: &mut Vec<Word>) -> Vec<(usize, Word)> {
     let mut output = Vec::new();
     loop {
-        let opcode = mem[ip] % 100;
-        let access = mem[ip] / 100;
-        println!("opcode: {} access: {} ip: {}", opcode, access, ip);
+        let opcode = machine.mem[machine.ip] % 100;
+        let access = machine.mem[machine.ip] / 100;
         match opcode {
             1 => {
-                let target = mem[addr(ip, 3)] as usize;
-                mem[target] = get(mem, ip, 1, access) + get(mem, ip, 2, access);
-                ip += 4;
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_add(get(machine, 2, access)).expect("Intcode overflow in add"));
+                machine.ip += 4;
             },
             2 => {
-                let target = mem[addr(ip, 3)] as usize;
-                println!("target: {}", target);
-                mem[target] = get(mem, ip, 1, access) * get(mem, ip, 2, access);
-                ip += 4;
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_mul(get(machine, 2, access)).expect("Intcode overflow in multiply"));
+                machine.ip += 4;
             },
             3 => {
-                let target = mem[addr(ip, 1)] as usize;
-                mem[target] = input.remove(0);
-                ip += 2;
+                if input.len() == 0 {
+                    return output;
+                }
+                let target = addr(machine, 1, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, input.remove(0));
+                machine.ip += 2;
             },
             4 => {
-                output.push(get(mem, ip, 1, access));
-                ip += 2;
+                output.push((machine.ip, get(machine, 1, access)));
+                machine.ip += 2;
             },
             5 => {
-                let val = get(mem, ip, 1, access);
+                let val = get(machine, 1, access);
                 if val != 0 {
-                    ip = get(mem, ip, 2, access) as usize;
+                    machine.ip = get(machine, 2, access) as usize;
                 } else {
-                    ip += 3;
+                    machine.ip += 3;
                 }
             },
             6 => {
-                let val = get(mem, ip, 1, access);
+                let val = get(machine, 1, access);
                 if val == 0 {
-                    ip = get(mem, ip, 2, access) as usize;
+                    machine.ip = get(machine, 2, access) as usize;
                 } else {
-                    ip += 3;
+                    machine.ip += 3;
                 }
             },
             7 => {
-                let target = mem[addr(ip, 3)] as usize;
-                mem[target] = if get(mem, ip, 1, access) < get(mem, ip, 2, access) {1} else {0};
-                ip += 4;
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) < get(machine, 2, access) {1} else {0});
+                machine.ip += 4;
             },
             8 => {
-                let target = mem[addr(ip, 3)] as usize;
-                mem[target] = if get(mem, ip, 1, access) == get(mem, ip, 2, access) {1} else {0};
-                ip += 4;
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) == get(machine, 2, access) {1} else {0});
+                machine.ip += 4;
+            },
+            9 => {
+                machine.relative_base = machine.relative_base.checked_add(get(machine, 1, access)).expect("Intcode overflow in relative base");
+                machine.ip += 2;
             },
             99 => {
                 return output;
             },
-            _ => panic!("Incorrect opcode")
+            _ => panic!("Incorrect opcode {}", opcode)
         }
     }
 }
 
-fn parse(line: &str) -> Vec<i32> {
+fn parse(line: &str) -> Vec<Word> {
     return line.split(",").map(|item| item.trim().parse()
-        .expect("Please type a number!")).collect::<Vec<i32>>();
+        .expect("Please type a number!")).collect::<Vec<Word>>();
+}
+
+// The TEST diagnostic outputs a result per self-test, zero meaning it passed, then halts
+// after outputting the diagnostic code.
+#[derive(PartialEq, Debug)]
+struct Diagnostic {
+    // (address of the output instruction, result) for each self-test
+    tests: Vec<(usize, Word)>,
+    // None if the program stopped before halting
+    code: Option<Word>
+}
+
+impl Diagnostic {
+    fn failures(&self) -> Vec<(usize, Word)> {
+        return self.tests.iter().filter(|(_, result)| *result != 0).cloned().collect();
+    }
+
+    fn passed(&self) -> bool {
+        return self.code.is_some() && self.failures().is_empty();
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let failures = self.failures();
+        writeln!(f, "{} of {} self-tests passed", self.tests.len() - failures.len(), self.tests.len())?;
+        for (address, result) in failures {
+            writeln!(f, "Self-test at instruction {} failed with {}", address, result)?;
+        }
+        return match self.code {
+            Some(code) => write!(f, "Diagnostic code {}", code),
+            None => write!(f, "No diagnostic code, the program is waiting for input")
+        };
+    }
+}
+
+fn diagnose(program: Vec<Word>, system_id: Word) -> Diagnostic {
+    let mut machine = Machine::new(program);
+    let mut output = run_traced(&mut machine, &mut vec![system_id]);
+    let halted = machine.mem[machine.ip] % 100 == 99;
+    let code = if halted {output.pop().map(|(_, code)| code)} else {None};
+    return Diagnostic { tests: output, code };
 }
 
 fn main() {
@@ -92,12 +187,15 @@ fn main() {
     let reader = BufReader::new(file);
     for line in reader.lines() {
         let line = line.expect("Failed to read");
-        let mut mem = parse(&line);
 
-        let mut input = Vec::new();
-        input.push(5);
-        let output = run(&mut mem, &mut input);
-        println!("{}", output.into_iter().map(|n| n.to_string()).collect::<Vec<String>>().join(","));
+        for system_id in &[1, 5] {
+            println!("System {}", system_id);
+            let diagnostic = diagnose(parse(&line), *system_id);
+            println!("{}", diagnostic);
+            if !diagnostic.passed() {
+                println!("System {} FAILED", system_id);
+            }
+        }
     }
 }
 
@@ -108,9 +206,9 @@ mod tests {
 
     fn test_io(start: &str, expected: &str, input: &str) {
         let expected = parse(expected);
-        let mut actual = parse(start);
+        let mut actual = Machine::new(parse(start));
         let mut input = parse(input);
-        let output = run(&mut actual, &mut input);
+        let output: Vec<Word> = run_traced(&mut actual, &mut input).into_iter().map(|(_, value)| value).collect();
         assert_eq!(output, expected);
     }
 
@@ -121,9 +219,9 @@ mod tests {
     
     fn test_mem(start: &str, expected: &str) {
         let expected = parse(expected);
-        let mut actual = parse(start);
-        run(&mut actual, &mut vec![]);
-        assert_eq!(actual, expected);
+        let mut actual = Machine::new(parse(start));
+        run_traced(&mut actual, &mut vec![]);
+        assert_eq!(actual.mem, expected);
     }
 
     #[test]
@@ -163,4 +261,66 @@ mod tests {
         test_io(prog_long, "1000", "8");
         test_io(prog_long, "1001", "12");
     }
+
+    #[test]
+    fn test_quine_to_output() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        test_io(quine, quine, "0"); // Ignores input
+    }
+
+    #[test]
+    fn test_large_values() {
+        test_io("1102,34915192,34915192,7,4,7,99,0", "1219070632396864", "0");
+        test_io("104,1125899906842624,99", "1125899906842624", "0");
+        test_io("3,0,4,0,99", "9223372036854775807", "9223372036854775807");
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in add")]
+    fn test_add_overflow() {
+        run_traced(&mut Machine::new(parse("3,9,1001,9,1,9,4,9,99,0")), &mut vec![Word::max_value()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in multiply")]
+    fn test_multiply_overflow() {
+        run_traced(&mut Machine::new(parse("1102,4294967296,2147483648,7,4,7,99,0")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in relative base")]
+    fn test_relative_base_overflow() {
+        run_traced(&mut Machine::new(parse("109,9223372036854775807,109,1,99")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in relative address")]
+    fn test_relative_address_overflow() {
+        run_traced(&mut Machine::new(parse("109,9223372036854775807,204,1,99")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Negative Intcode address -5")]
+    fn test_negative_relative_address() {
+        run_traced(&mut Machine::new(parse("109,-5,204,0,99")), &mut vec![]);
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let line = std::fs::read_to_string("input").expect("Failed to open input");
+        let report = diagnose(parse(line.trim()), 1);
+        assert!(report.passed());
+        assert_eq!(report.tests.len(), 9);
+
+        // Outputs 0 then 3 as self-tests, then the diagnostic code 42
+        let report = diagnose(parse("104,0,104,3,104,42,99"), 1);
+        assert_eq!(report, Diagnostic { tests: vec![(0, 0), (2, 3)], code: Some(42) });
+        assert!(!report.passed());
+        assert_eq!(report.to_string(), "1 of 2 self-tests passed\nSelf-test at instruction 2 failed with 3\nDiagnostic code 42");
+
+        // Waits for a second input rather than halting
+        let report = diagnose(parse("3,0,104,0,3,0,99"), 1);
+        assert_eq!(report, Diagnostic { tests: vec![(2, 0)], code: None });
+        assert!(!report.passed());
+    }
 }
@@ -1,59 +1,95 @@
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 
+use rayon::prelude::*;
+
+type Word = i64;
+
 struct Machine {
-    mem: Vec<i32>,
-    ip: usize
+    mem: Vec<Word>,
+    ip: usize,
+    relative_base: Word
 }
 
 
 impl Machine
 {
-    pub fn new(mem: Vec<i32>) -> Machine {
-        Machine { mem: mem, ip: 0 }
+    pub fn new(mem: Vec<Word>) -> Machine {
+        Machine { mem: mem, ip: 0, relative_base: 0 }
+    }
+}
+
+type AddrOrImm = Result<usize, Word>;
+
+fn get_at(machine: &Machine, addr: usize) -> Word {
+    if addr >= machine.mem.len() {
+        return 0;
     }
+    return machine.mem[addr];
 }
 
-fn addr(machine: &Machine, offset: i32) -> usize {
-    return (machine.ip as i32 + offset) as usize;
+fn to_address(value: Word) -> usize {
+    if value < 0 {
+        panic!("Negative Intcode address {}", value);
+    }
+    return value as usize;
 }
 
-fn get(machine: &Machine, offset: i32, access: i32) -> i32 {
+fn addr(machine: &Machine, offset: Word, access: Word) -> AddrOrImm {
     let mut a = access;
     for _ in 1..offset {
         a /= 10;
     }
     a = a % 10;
-    let addr = addr(machine, offset);
+    let instr_value = get_at(machine, machine.ip + offset as usize);
+    //println!("instr_value: {}, offset: {}, access: {}", instr_value, offset, access);
     return match a {
-        0 => machine.mem[machine.mem[addr] as usize],
-        1 => machine.mem[addr],
+        0 => Ok(to_address(instr_value)),
+        1 => Err(instr_value),
+        2 => Ok(to_address(machine.relative_base.checked_add(instr_value).expect("Intcode overflow in relative address"))),
         _ => panic!("Unknown access type {} (offset: {} access: {})", a, offset, access)
     };
 }
 
-fn run(machine: &mut Machine, input: &mut Vec<i32>) -> Vec<i32> {
+fn get(machine: &Machine, offset: Word, access: Word) -> Word {
+    let a = addr(machine, offset, access);
+    return match a {
+        Ok(address) => get_at(machine, address),
+        Err(immediate) => immediate
+    };
+}
+
+fn set(machine: &mut Machine, offset: Word, to: Word) {
+    //println!("Set {} to {}", offset, to);
+    let offset_as_usize = offset as usize;
+    if machine.mem.len() <= offset_as_usize {
+        machine.mem.resize(offset_as_usize + 1, 0);
+    }
+    machine.mem[offset_as_usize] = to;
+}
+
+fn run(machine: &mut Machine, input: &mut Vec<Word>) -> Vec<Word> {
     let mut output = Vec::new();
     loop {
         let opcode = machine.mem[machine.ip] % 100;
         let access = machine.mem[machine.ip] / 100;
         match opcode {
             1 => {
-                let target = machine.mem[addr(machine, 3)] as usize;
-                machine.mem[target] = get(machine, 1, access) + get(machine, 2, access);
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_add(get(machine, 2, access)).expect("Intcode overflow in add"));
                 machine.ip += 4;
             },
             2 => {
-                let target = machine.mem[addr(machine, 3)] as usize;
-                machine.mem[target] = get(machine, 1, access) * get(machine, 2, access);
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, get(machine, 1, access).checked_mul(get(machine, 2, access)).expect("Intcode overflow in multiply"));
                 machine.ip += 4;
             },
             3 => {
                 if input.len() == 0 {
                     return output;
                 }
-                let target = machine.mem[addr(machine, 1)] as usize;
-                machine.mem[target] = input.remove(0);
+                let target = addr(machine, 1, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, input.remove(0));
                 machine.ip += 2;
             },
             4 => {
@@ -77,85 +113,132 @@ fn run(machine: &mut Machine, input: &mut Vec<i32>) -> Vec<i32> {
                 }
             },
             7 => {
-                let target = machine.mem[addr(machine, 3)] as usize;
-                machine.mem[target] = if get(machine, 1, access) < get(machine, 2, access) {1} else {0};
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) < get(machine, 2, access) {1} else {0});
                 machine.ip += 4;
             },
             8 => {
-                let target = machine.mem[addr(machine, 3)] as usize;
-                machine.mem[target] = if get(machine, 1, access) == get(machine, 2, access) {1} else {0};
+                let target = addr(machine, 3, access).expect("Can't write to an immediate") as Word;
+                set(machine, target, if get(machine, 1, access) == get(machine, 2, access) {1} else {0});
                 machine.ip += 4;
             },
+            9 => {
+                machine.relative_base = machine.relative_base.checked_add(get(machine, 1, access)).expect("Intcode overflow in relative base");
+                machine.ip += 2;
+            },
             99 => {
                 return output;
             },
-            _ => panic!("Incorrect opcode")
+            _ => panic!("Incorrect opcode {}", opcode)
         }
     }
 }
 
-fn parse(line: &str) -> Vec<i32> {
+fn parse(line: &str) -> Vec<Word> {
     return line.split(",").map(|item| item.trim().parse()
-        .expect("Please type a number!")).collect::<Vec<i32>>();
+        .expect("Please type a number!")).collect::<Vec<Word>>();
+}
+
+// Heap's algorithm, yielding each permutation of `items` exactly once by swapping one pair at a time.
+struct Permutations {
+    items: Vec<Word>,
+    counters: Vec<usize>,
+    i: usize,
+    started: bool
+}
+
+fn permutations(of: &[Word]) -> Permutations {
+    return Permutations { items: of.to_vec(), counters: vec![0; of.len()], i: 1, started: false };
 }
 
-fn permutations(of: Vec<i32>) -> Vec<Vec<i32>> {
-    if of.len() == 1 {
-        return vec![of];
-    } else {
-        let mut result: Vec<Vec<i32>> = Vec::new();
-        for i in 0..of.len() {
-            let mut without = of.clone();
-            without.remove(i);
-            let ps = permutations(without);
-            for mut p in ps {
-                p.push(of[i]);
-                result.push(p);
+impl Iterator for Permutations {
+    type Item = Vec<Word>;
+
+    fn next(&mut self) -> Option<Vec<Word>> {
+        if !self.started {
+            self.started = true;
+            return Some(self.items.clone());
+        }
+        while self.i < self.items.len() {
+            if self.counters[self.i] < self.i {
+                let j = if self.i % 2 == 0 {0} else {self.counters[self.i]};
+                self.items.swap(j, self.i);
+                self.counters[self.i] += 1;
+                self.i = 1;
+                return Some(self.items.clone());
             }
+            self.counters[self.i] = 0;
+            self.i += 1;
         }
-        return result;
-    }       
+        return None;
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Wiring {
+    // Each amplifier runs once and the last one's output drives the thrusters
+    Series,
+    // The last amplifier feeds back into the first until it halts
+    Feedback
 }
 
-fn runPhases(line: &str, p: &Vec<i32>) -> i32 {
-    let mut amps: Vec<Machine> = Vec::new();
+// A chain of amplifiers all running the same program, one per phase setting.
+struct Circuit {
+    program: Vec<Word>,
+    wiring: Wiring
+}
 
-    let mut o = 0;
-    let mut done = false;
-    let mut lastO = 0;
-    while !done {
-        let mut amp = 0;
-        for x in p {
-            let mut input = Vec::new();
+fn halted(machine: &Machine) -> bool {
+    return machine.mem[machine.ip] % 100 == 99;
+}
 
-            if amp >= amps.len() {
-                amps.push(Machine::new(parse(&line)));
-                input.push(*x);
-            }
-            
-            let ampMem = &mut amps[amp];
-
-            input.push(o);
-            let mut output = run(ampMem, &mut input);
-            if output.len() == 0 {
-                done = true;
-            } else {
-                while output.len() > 0 {
-                    o = output.remove(0);
-                    input.push(o);
+impl Circuit {
+    fn new(line: &str, wiring: Wiring) -> Circuit {
+        return Circuit { program: parse(line), wiring };
+    }
+
+    // The thruster signal for the given phases, or None if there are no amplifiers or the
+    // chain never produces one or stalls waiting for input.
+    fn signal(&self, phases: &[Word]) -> Option<Word> {
+        let stages = phases.len();
+        if stages == 0 {
+            return None;
+        }
+        let mut amps: Vec<Machine> = phases.iter().map(|_| Machine::new(self.program.clone())).collect();
+        let mut inputs: Vec<Vec<Word>> = phases.iter().map(|&phase| vec![phase]).collect();
+        inputs[0].push(0);
+
+        let mut thrusters = None;
+        loop {
+            let mut progress = false;
+            for amp in 0..stages {
+                let mut input = std::mem::take(&mut inputs[amp]);
+                let output = run(&mut amps[amp], &mut input);
+                progress |= !output.is_empty();
+                if amp == stages - 1 {
+                    thrusters = output.last().cloned().or(thrusters);
                 }
-                if amp == 4 {
-                    lastO = o;
+                if amp < stages - 1 || self.wiring == Wiring::Feedback {
+                    inputs[(amp + 1) % stages].extend(output);
                 }
             }
-            //println!("- {} {} {} {}", amp, o, done, input.len()); 
-            amp+=1;
+            if self.wiring == Wiring::Series || halted(&amps[stages - 1]) {
+                return thrusters;
+            }
+            if !progress {
+                return None;
+            }
         }
     }
-    //println!("END {} {} {:?}", lastO, o, p);
-    return lastO;
-}
 
+    // Tries every ordering of the phase set (one amplifier per phase) in parallel, returning the
+    // ordering with the highest thruster signal. Ties go to the lexicographically smallest ordering.
+    fn best(&self, phase_set: &[Word]) -> Option<(Vec<Word>, Word)> {
+        return permutations(phase_set).par_bridge()
+            .filter_map(|phases| self.signal(&phases).map(|signal| (phases, signal)))
+            .max_by(|(a, a_signal), (b, b_signal)| a_signal.cmp(b_signal).then(b.cmp(a)));
+    }
+}
 
 fn main() {
     let file = File::open("input").expect("Failed to open input");
@@ -163,29 +246,15 @@ fn main() {
     for line in reader.lines() {
         let line = line.expect("Failed to read");
 
-
-        /*for p in permutations(vec![0,1,2,3,4]) {
-            let mut o = 0;
-            for x in &p {
-                let mut mem = parse(&line);
-
-                let mut input = Vec::new();
-                input.push(*x);
-                input.push(o);
-                let output = run(&mut mem, &mut input);
-                o = output[0];
+        for (wiring, phase_set) in &[(Wiring::Series, [0, 1, 2, 3, 4]), (Wiring::Feedback, [5, 6, 7, 8, 9])] {
+            match Circuit::new(&line, *wiring).best(phase_set) {
+                Some((phases, signal)) => println!("{:?}: {} {:?}", wiring, signal, phases),
+                None => println!("{:?}: no phase setting produces a signal", wiring)
             }
-            println!("{} {:?}", o, p);
-        }*/
-
-        for p in permutations(vec![5,6,7,8,9]) {
-            let output = runPhases(&line, &p);
-            println!("{} {:?}", output, p);
         }
     }
 }
 
-/*
 #[cfg(test)]
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
@@ -193,7 +262,7 @@ mod tests {
 
     fn test_io(start: &str, expected: &str, input: &str) {
         let expected = parse(expected);
-        let mut actual = parse(start);
+        let mut actual = Machine::new(parse(start));
         let mut input = parse(input);
         let output = run(&mut actual, &mut input);
         assert_eq!(output, expected);
@@ -206,9 +275,9 @@ mod tests {
     
     fn test_mem(start: &str, expected: &str) {
         let expected = parse(expected);
-        let mut actual = parse(start);
+        let mut actual = Machine::new(parse(start));
         run(&mut actual, &mut vec![]);
-        assert_eq!(actual, expected);
+        assert_eq!(actual.mem, expected);
     }
 
     #[test]
@@ -249,8 +318,96 @@ mod tests {
         test_io(prog_long, "1001", "12");
     }
 
+    #[test]
+    fn test_permutations() {
+        let all: Vec<Vec<Word>> = permutations(&[0, 1, 2, 3]).collect();
+        assert_eq!(all.len(), 24);
+        let mut sorted = all.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), 24);
+        assert_eq!(all[0], vec![0, 1, 2, 3]);
+        assert_eq!(permutations(&[7]).collect::<Vec<_>>(), vec![vec![7]]);
+    }
+
+    #[test]
+    fn test_series() {
+        let circuit = Circuit::new("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0", Wiring::Series);
+        assert_eq!(circuit.signal(&[4, 3, 2, 1, 0]), Some(43210));
+        assert_eq!(circuit.best(&[0, 1, 2, 3, 4]), Some((vec![4, 3, 2, 1, 0], 43210)));
+
+        let circuit = Circuit::new("3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0", Wiring::Series);
+        assert_eq!(circuit.best(&[0, 1, 2, 3, 4]), Some((vec![1, 0, 4, 3, 2], 65210)));
+    }
+
     #[test]
     fn test_phase_programs() {
-        assert_eq!(runPhases("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5", vec![9, 8, 7, 6, 5]), 139629729);
+        let circuit = Circuit::new("3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5", Wiring::Feedback);
+        assert_eq!(circuit.signal(&[9, 8, 7, 6, 5]), Some(139629729));
+        assert_eq!(circuit.best(&[5, 6, 7, 8, 9]), Some((vec![9, 8, 7, 6, 5], 139629729)));
+    }
+
+    #[test]
+    fn test_other_stage_counts() {
+        // Each amplifier adds its phase to the signal
+        let circuit = Circuit::new("3,11,3,12,1,11,12,12,4,12,99,0,0", Wiring::Series);
+        assert_eq!(circuit.signal(&[1, 2, 3]), Some(6));
+        assert_eq!(circuit.best(&[5, 1]), Some((vec![1, 5], 6)));
+
+        // An amplifier that never outputs leaves the thrusters without a signal
+        let circuit = Circuit::new("3,0,3,0,99", Wiring::Feedback);
+        assert_eq!(circuit.best(&[0, 1, 2]), None);
+    }
+
+    #[test]
+    fn test_no_amplifiers() {
+        for wiring in &[Wiring::Series, Wiring::Feedback] {
+            let circuit = Circuit::new("3,11,3,12,1,11,12,12,4,12,99,0,0", *wiring);
+            assert_eq!(circuit.signal(&[]), None);
+            assert_eq!(circuit.best(&[]), None);
+        }
+    }
+
+    #[test]
+    fn test_quine_to_output() {
+        let quine = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
+        test_io(quine, quine, "0"); // Ignores input
     }
-}*/
+
+    #[test]
+    fn test_large_values() {
+        test_io("1102,34915192,34915192,7,4,7,99,0", "1219070632396864", "0");
+        test_io("104,1125899906842624,99", "1125899906842624", "0");
+        test_io("3,0,4,0,99", "9223372036854775807", "9223372036854775807");
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in add")]
+    fn test_add_overflow() {
+        run(&mut Machine::new(parse("3,9,1001,9,1,9,4,9,99,0")), &mut vec![Word::max_value()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in multiply")]
+    fn test_multiply_overflow() {
+        run(&mut Machine::new(parse("1102,4294967296,2147483648,7,4,7,99,0")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in relative base")]
+    fn test_relative_base_overflow() {
+        run(&mut Machine::new(parse("109,9223372036854775807,109,1,99")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Intcode overflow in relative address")]
+    fn test_relative_address_overflow() {
+        run(&mut Machine::new(parse("109,9223372036854775807,204,1,99")), &mut vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Negative Intcode address -5")]
+    fn test_negative_relative_address() {
+        run(&mut Machine::new(parse("109,-5,204,0,99")), &mut vec![]);
+    }
+}
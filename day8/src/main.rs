@@ -35,6 +35,10 @@ impl Image {
         }
         panic!("Pixel at {}, {} is maformed", x, y);
     }
+
+    pub fn bitmap(self: &Image) -> Vec<Vec<bool>> {
+        return (0..self.height).map(|y| (0..self.width).map(|x| self.get(x, y) == 1).collect()).collect();
+    }
 }
 
 fn main() {
@@ -69,6 +73,11 @@ fn main() {
             if image.get(x, y) == 1 {"*"} else {" "}
         ).collect::<Vec<&str>>().join(""));
     }
+
+    match glyphs::read(&image.bitmap()) {
+        Ok(text) => println!("{}", text),
+        Err(unrecognized) => println!("{}", unrecognized)
+    }
 }
 
 #[cfg(test)]
@@ -79,4 +88,17 @@ mod tests {
     #[test]
     fn test_load() {
     }
+
+    #[test]
+    fn test_bitmap() {
+        let image = Image::new("0222112222120000", 2, 2);
+        assert_eq!(image.layers, 4);
+        assert_eq!(image.bitmap(), vec![vec![false, true], vec![true, false]]);
+    }
+
+    #[test]
+    fn test_message() {
+        let image = Image::new(&fs::read_to_string("input").expect("Couldn't read input").trim(), 25, 6);
+        assert_eq!(glyphs::read(&image.bitmap()), Ok("PCULA".to_string()));
+    }
 }
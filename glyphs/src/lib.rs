@@ -0,0 +1,148 @@
+// Block capitals as drawn by Advent of Code puzzles, trimmed to their lit columns.
+const GLYPHS: [(char, [&str; 6]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', ["###", ".#.", ".#.", ".#.", ".#.", "###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"])
+];
+
+#[derive(PartialEq, Debug)]
+pub struct Unrecognized {
+    // The text read, with '?' standing in for each unknown glyph
+    pub text: String,
+    // The first column of each unknown glyph
+    pub columns: Vec<usize>
+}
+
+impl std::fmt::Display for Unrecognized {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        return write!(f, "Couldn't read {} (unknown glyphs at columns {:?})", self.text, self.columns);
+    }
+}
+
+// Letters are drawn on a fixed pitch, usually with a blank column between them, but a
+// letter as wide as its cell (like Y) touches the next one.
+const PITCH: usize = 5;
+
+// Reads a bitmap of rows as block capitals, cutting it into cells PITCH columns wide.
+// Whichever alignment of the cells reads the most letters wins.
+pub fn read(bitmap: &[Vec<bool>]) -> Result<String, Unrecognized> {
+    let has_lit = |row: &Vec<bool>| row.iter().any(|p| *p);
+    let (first, last) = match (bitmap.iter().position(has_lit), bitmap.iter().rposition(has_lit)) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Ok(String::new())
+    };
+    let rows = &bitmap[first..=last];
+    let width = rows.iter().map(|row| row.len()).max().unwrap();
+    let is_lit = |x: usize, y: usize| *rows[y].get(x).unwrap_or(&false);
+    let lit_column = |x: &usize| (0..rows.len()).any(|y| is_lit(*x, y));
+    let first_lit = (0..width).find(lit_column).unwrap();
+
+    let read_cells = |origin: usize| {
+        let mut bounds = vec![0];
+        bounds.extend((origin..width).step_by(PITCH).filter(|x| *x > 0));
+        bounds.push(width);
+        let mut text = String::new();
+        let mut columns = vec![];
+        for cell in bounds.windows(2) {
+            let lit: Vec<usize> = (cell[0]..cell[1]).filter(lit_column).collect();
+            let (start, end) = match (lit.first(), lit.last()) {
+                (Some(start), Some(end)) => (*start, *end),
+                _ => continue
+            };
+            let glyph: Vec<String> = (0..rows.len()).map(|y|
+                (start..=end).map(|gx| if is_lit(gx, y) {'#'} else {'.'}).collect()
+            ).collect();
+            match GLYPHS.iter().find(|(_, g)| g.iter().eq(glyph.iter())) {
+                Some((c, _)) => text.push(*c),
+                None => {
+                    text.push('?');
+                    columns.push(start);
+                }
+            }
+        }
+        return (text, columns);
+    };
+
+    // Letters start at the left of their cell, so try lining up with the first lit column first
+    let (text, columns) = (0..PITCH).map(|i| read_cells((first_lit + i) % PITCH))
+        .min_by_key(|(_, columns)| columns.len()).unwrap();
+    return if columns.is_empty() {
+        Ok(text)
+    } else {
+        Err(Unrecognized { text, columns })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+
+    fn bitmap(rows: &[&str]) -> Vec<Vec<bool>> {
+        return rows.iter().map(|row| row.chars().map(|c| c == '#').collect()).collect();
+    }
+
+    #[test]
+    fn test_read() {
+        let image = bitmap(&[
+            "..........................",
+            "###...##..#..#.#.....##...",
+            "#..#.#..#.#..#.#....#..#..",
+            "#..#.#....#..#.#....#..#..",
+            "###..#....#..#.#....####..",
+            "#....#..#.#..#.#....#..#..",
+            "#.....##...##..####.#..#..",
+            ".........................."
+        ]);
+        assert_eq!(read(&image), Ok("PCULA".to_string()));
+    }
+
+    #[test]
+    fn test_every_glyph() {
+        for (c, rows) in GLYPHS.iter() {
+            assert_eq!(read(&bitmap(rows)), Ok(c.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_unrecognized() {
+        let image = bitmap(&[
+            "..##..###..###....",
+            ".#..#.#..#.#..#...",
+            ".#....###..#..#...",
+            ".####.#..#.###....",
+            ".#..#.#..#.#.#....",
+            ".#..#.###..#..#..."
+        ]);
+        assert_eq!(read(&image), Err(Unrecognized { text: "?BR".to_string(), columns: vec![1] }));
+        assert_eq!(read(&bitmap(&["...", "..."])), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_touching_letters() {
+        let image = bitmap(&[
+            "#...#.##.",
+            "#...##..#",
+            ".#.#.#..#",
+            "..#..####",
+            "..#..#..#",
+            "..#..#..#"
+        ]);
+        assert_eq!(read(&image), Ok("YA".to_string()));
+    }
+}